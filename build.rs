@@ -20,6 +20,18 @@ fn main() {
             "plus",
             "search-global",
             "loupe",
+            "clock",
+            "edit-copy",
+            "media-record",
+            "open-menu",
+            "calendar",
+            "user-trash",
+            "moon",
+            "cross",
+            "alarm",
+            "audio-x-generic",
+            "object-select",
+            "offline-globe",
         ],
     );
 }
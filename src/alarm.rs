@@ -0,0 +1,141 @@
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Station, saver, scheduler};
+
+// how long an alarm takes to ramp up to full volume
+pub const FADE_SECONDS: u32 = 30;
+pub const SNOOZE_SECONDS: i64 = 9 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alarm {
+    pub station: Station,
+    // Monday first, same as glib::DateTime::day_of_week
+    pub weekdays: [bool; 7],
+    // minutes since midnight
+    pub time: u32,
+}
+
+impl Alarm {
+    // weekday is 1 (Monday) to 7 (Sunday)
+    pub fn is_due(&self, weekday: usize, minute: u32) -> bool {
+        self.weekdays[weekday - 1] && minute == self.time
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {}\n{}",
+            scheduler::format_time(self.time),
+            self.station.name,
+            scheduler::describe_weekdays(self.weekdays)
+        )
+    }
+}
+
+// Played instead of the station when the stream won't play, so a dead
+// station can't mean a missed alarm
+const SOUND: &[u8] = include_bytes!("../data/alarm.wav");
+
+#[derive(Debug)]
+pub struct Tone {
+    pipeline: gst::Element,
+    _watch: gst::bus::BusWatchGuard,
+}
+
+impl Tone {
+    pub fn play() -> Result<Self, Error> {
+        // playbin wants a uri, so the bundled sound goes in the cache first
+        let path = saver::get_cache_dir().join("alarm.wav");
+        std::fs::create_dir_all(saver::get_cache_dir())
+            .and_then(|_| std::fs::write(&path, SOUND))
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        let uri = glib::filename_to_uri(&path, None).map_err(|e| Error::Storage(e.to_string()))?;
+        let pipeline = gst::ElementFactory::make("playbin")
+            .property("uri", uri.as_str())
+            .build()
+            .map_err(|e| Error::Stream {
+                url: "alarm sound".to_string(),
+                domain: "gst-element".to_string(),
+                message: e.to_string(),
+            })?;
+
+        // start again from the top until the alarm is stopped
+        let weak = pipeline.downgrade();
+        let _watch = pipeline
+            .bus()
+            .expect("playbin always has a bus")
+            .add_watch_local(move |_, message| {
+                if let gst::MessageView::Eos(_) = message.view()
+                    && let Some(pipeline) = weak.upgrade()
+                {
+                    let _ = pipeline.seek_simple(gst::SeekFlags::FLUSH, gst::ClockTime::ZERO);
+                }
+                glib::ControlFlow::Continue
+            })
+            .map_err(|e| Error::Stream {
+                url: "alarm sound".to_string(),
+                domain: "gst-bus".to_string(),
+                message: e.to_string(),
+            })?;
+
+        pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|e| Error::Stream {
+                url: "alarm sound".to_string(),
+                domain: "gst-state-change".to_string(),
+                message: e.to_string(),
+            })?;
+        Ok(Self { pipeline, _watch })
+    }
+}
+
+impl Drop for Tone {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm(key: &str, url: &str) -> Alarm {
+        Alarm {
+            station: Station {
+                name: key.to_string(),
+                url: url.to_string(),
+                key: Some(key.to_string()),
+                ..Default::default()
+            },
+            weekdays: [true; 7],
+            time: scheduler::parse_time("07:00").unwrap(),
+        }
+    }
+
+    #[test]
+    fn follows_edits_and_merges() {
+        let mut alarms = [
+            alarm("edited", "http://example.com/stream"),
+            alarm("removed", "http://example.com/other"),
+        ];
+        let edited = Station {
+            url: "http://example.com/moved".to_string(),
+            ..alarms[0].station.clone()
+        };
+        let before = alarms[0].station.clone();
+        let stations = alarms.iter_mut().map(|alarm| &mut alarm.station);
+        assert!(scheduler::replace_station(stations, &before, &edited));
+        assert_eq!(alarms[0].station.url, "http://example.com/moved");
+        assert!(scheduler::same_station(&alarms[0].station, &edited));
+
+        let removed = alarms[1].station.clone();
+        let kept = alarm("kept", "https://example.com/other/").station;
+        let stations = alarms.iter_mut().map(|alarm| &mut alarm.station);
+        assert!(scheduler::replace_station(stations, &removed, &kept));
+        assert_eq!(alarms[0].station.key.as_deref(), Some("edited"));
+        assert_eq!(alarms[1].station.key.as_deref(), Some("kept"));
+        assert!(alarms[1].describe().starts_with("07:00 kept"));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::Station;
+
+// http and https, a trailing slash, upper case hosts and default ports all
+// get to the same stream as far as we're concerned
+pub fn normalise_url(url: &str) -> String {
+    let url = url.trim();
+    match reqwest::Url::parse(url) {
+        // parsing already lowercases the host and drops default ports
+        Ok(parsed) => {
+            let port = parsed
+                .port()
+                .map(|port| format!(":{port}"))
+                .unwrap_or_default();
+            let query = parsed
+                .query()
+                .map(|query| format!("?{query}"))
+                .unwrap_or_default();
+            format!(
+                "{}{port}{}{query}",
+                parsed.host_str().unwrap_or_default(),
+                parsed.path().trim_end_matches('/')
+            )
+        }
+        Err(_) => url.trim_end_matches('/').to_lowercase(),
+    }
+}
+
+// every url the station goes by, normalised
+fn urls(station: &Station) -> Vec<String> {
+    [Some(&station.url), station.original_url.as_ref()]
+        .into_iter()
+        .flatten()
+        .chain(&station.aliases)
+        .map(|url| normalise_url(url))
+        .collect()
+}
+
+// Same radio-browser station, or any of the urls either was added with
+// point at the same place
+pub fn is_duplicate(a: &Station, b: &Station) -> bool {
+    if let (Some(a), Some(b)) = (&a.uuid, &b.uuid)
+        && a == b
+    {
+        return true;
+    }
+    let a_urls = urls(a);
+    urls(b).iter().any(|url| a_urls.contains(url))
+}
+
+// Fills in whatever station is missing from other. Name, url and group stay
+// as they are, any of other's urls station doesn't already go by become
+// aliases.
+pub fn merge(station: &mut Station, other: Station) {
+    let mut known = urls(station);
+    for url in [Some(other.url), other.original_url]
+        .into_iter()
+        .flatten()
+        .chain(other.aliases)
+    {
+        let normalised = normalise_url(&url);
+        if !known.contains(&normalised) {
+            known.push(normalised);
+            station.aliases.push(url);
+        }
+    }
+    station.group = station.group.take().or(other.group);
+    station.uuid = station.uuid.take().or(other.uuid);
+    for tag in other.tags {
+        if !station.tags.contains(&tag) {
+            station.tags.push(tag);
+        }
+    }
+    station.country = station.country.take().or(other.country);
+    station.language = station.language.take().or(other.language);
+    station.codec = station.codec.take().or(other.codec);
+    station.bitrate = station.bitrate.or(other.bitrate);
+    station.homepage = station.homepage.take().or(other.homepage);
+    station.favicon = station.favicon.take().or(other.favicon);
+    station.notes = match (station.notes.take(), other.notes) {
+        (Some(notes), Some(other_notes)) if notes != other_notes => {
+            Some(format!("{notes}\n\n{other_notes}"))
+        }
+        (notes, other_notes) => notes.or(other_notes),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(url: &str) -> Station {
+        Station {
+            name: url.to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_stream_different_spelling() {
+        assert_eq!(
+            normalise_url("https://Example.COM:443/live/"),
+            normalise_url("http://example.com/live")
+        );
+        assert_eq!(
+            normalise_url(" http://example.com:80/live?mount=1 "),
+            "example.com/live?mount=1"
+        );
+        assert_ne!(
+            normalise_url("http://example.com:8000/live"),
+            normalise_url("http://example.com/live")
+        );
+        assert_ne!(
+            normalise_url("http://example.com/live?mount=1"),
+            normalise_url("http://example.com/live?mount=2")
+        );
+        assert_eq!(normalise_url("Not A URL/"), "not a url");
+    }
+
+    #[test]
+    fn duplicates() {
+        assert!(is_duplicate(
+            &station("http://example.com/live"),
+            &station("https://example.com/live/")
+        ));
+        assert!(!is_duplicate(
+            &station("http://example.com/live"),
+            &station("http://example.com/other")
+        ));
+
+        // added from a playlist that resolved to the other one's stream
+        let resolved = Station {
+            original_url: Some("http://example.com/listen.pls".to_string()),
+            ..station("http://stream.example.com/live")
+        };
+        assert!(is_duplicate(
+            &resolved,
+            &station("http://example.com/listen.pls")
+        ));
+
+        let uuid = |uuid: &str, url: &str| Station {
+            uuid: Some(uuid.to_string()),
+            ..station(url)
+        };
+        assert!(is_duplicate(
+            &uuid("a", "http://one"),
+            &uuid("a", "http://two")
+        ));
+        assert!(!is_duplicate(
+            &uuid("a", "http://one"),
+            &uuid("b", "http://two")
+        ));
+    }
+
+    #[test]
+    fn merge_fills_gaps() {
+        let mut kept = Station {
+            name: "Kept".to_string(),
+            group: Some("News".to_string()),
+            tags: vec!["news".to_string()],
+            bitrate: Some(128),
+            notes: Some("mine".to_string()),
+            ..station("http://one")
+        };
+        let other = Station {
+            name: "Other".to_string(),
+            group: Some("Music".to_string()),
+            uuid: Some("uuid".to_string()),
+            tags: vec!["news".to_string(), "talk".to_string()],
+            country: Some("UK".to_string()),
+            bitrate: Some(320),
+            notes: Some("theirs".to_string()),
+            ..station("http://two")
+        };
+        merge(&mut kept, other);
+        assert_eq!(kept.name, "Kept");
+        assert_eq!(kept.url, "http://one");
+        assert_eq!(kept.group.as_deref(), Some("News"));
+        assert_eq!(kept.uuid.as_deref(), Some("uuid"));
+        assert_eq!(kept.tags, ["news", "talk"]);
+        assert_eq!(kept.country.as_deref(), Some("UK"));
+        assert_eq!(kept.bitrate, Some(128));
+        assert_eq!(kept.notes.as_deref(), Some("mine\n\ntheirs"));
+        assert_eq!(kept.aliases, ["http://two"]);
+        assert!(is_duplicate(&kept, &station("https://two/")));
+
+        let mut same_notes = Station {
+            notes: Some("same".to_string()),
+            ..station("http://one")
+        };
+        merge(
+            &mut same_notes,
+            Station {
+                notes: Some("same".to_string()),
+                ..station("http://one")
+            },
+        );
+        assert_eq!(same_notes.notes.as_deref(), Some("same"));
+        assert!(same_notes.aliases.is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_every_url() {
+        let mut kept = Station {
+            original_url: Some("http://one/listen.pls".to_string()),
+            ..station("http://one/live")
+        };
+        let other = Station {
+            original_url: Some("http://two/listen.m3u".to_string()),
+            aliases: vec!["http://three".to_string(), "http://one/live/".to_string()],
+            ..station("http://two/live")
+        };
+        merge(&mut kept, other);
+        assert_eq!(kept.url, "http://one/live");
+        assert_eq!(kept.original_url.as_deref(), Some("http://one/listen.pls"));
+        assert_eq!(
+            kept.aliases,
+            ["http://two/live", "http://two/listen.m3u", "http://three"]
+        );
+        for url in ["http://two/live", "http://two/listen.m3u", "http://three"] {
+            assert!(is_duplicate(&kept, &station(url)));
+        }
+    }
+}
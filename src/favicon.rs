@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use relm4::gtk::glib;
+
+use crate::saver;
+
+// anything bigger than this isn't an icon
+const MAX_SIZE: usize = 512 * 1024;
+
+// Favicons are stored under a hash of their url, so two stations sharing
+// one only download it once
+fn cache_path(url: &str) -> PathBuf {
+    let name = glib::compute_checksum_for_string(glib::ChecksumType::Sha1, url)
+        .map(|hash| hash.to_string())
+        .unwrap_or_else(|| url.replace('/', "_"));
+    saver::get_cache_dir().join("favicons").join(name)
+}
+
+pub fn cached(url: &str) -> Option<PathBuf> {
+    let path = cache_path(url);
+    path.is_file().then_some(path)
+}
+
+// Favicons are only decoration, so anything going wrong here just means the
+// station keeps the generic icon
+pub async fn fetch(url: &str) -> Option<PathBuf> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .ok()?;
+    let mut response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+    let is_image = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("image/"));
+    if !is_image {
+        return None;
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_SIZE as u64)
+    {
+        return None;
+    }
+    // the length isn't always given, so stop reading once it is too big
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.ok()? {
+        data.extend_from_slice(&chunk);
+        if data.len() > MAX_SIZE {
+            return None;
+        }
+    }
+
+    let path = cache_path(url);
+    fs::create_dir_all(path.parent()?).ok()?;
+    fs::write(&path, data).ok()?;
+    Some(path)
+}
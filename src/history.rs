@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gtk::prelude::*;
+use relm4::{
+    gtk::{self, glib, pango},
+    typed_view::list::{RelmListItem, TypedListView},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Station, icon_names, saver, streamer::NowPlaying};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // the station's name when the track played, only for showing
+    pub station: String,
+    // radio-browser's id for the station, older entries don't have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub url: String,
+    pub track: String,
+    // seconds since the unix epoch
+    pub timestamp: i64,
+}
+
+impl HistoryEntry {
+    // Which station the entry belongs to. Names get edited and needn't be
+    // unique, so this is the radio-browser uuid or failing that the url.
+    fn key(&self) -> &str {
+        self.uuid.as_deref().unwrap_or(&self.url)
+    }
+
+    fn time(&self) -> String {
+        glib::DateTime::from_unix_local(self.timestamp)
+            .and_then(|time| time.format("%x %X"))
+            .map(|time| time.to_string())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryItem {
+    entry: HistoryEntry,
+    handler: Option<glib::SignalHandlerId>,
+}
+
+pub struct HistoryWidgets {
+    label: gtk::Label,
+    copy_button: gtk::Button,
+}
+
+impl RelmListItem for HistoryItem {
+    type Root = gtk::Box;
+    type Widgets = HistoryWidgets;
+
+    fn setup(_item: &gtk::ListItem) -> (gtk::Box, HistoryWidgets) {
+        relm4::view! {
+            my_box = gtk::Box {
+                set_spacing: 2,
+                set_orientation: gtk::Orientation::Horizontal,
+                #[name = "label"]
+                gtk::Label {
+                    set_halign: gtk::Align::Start,
+                    set_use_markup: true,
+                    set_ellipsize: pango::EllipsizeMode::End,
+                },
+                #[name = "copy_button"]
+                gtk::Button {
+                    set_halign: gtk::Align::End,
+                    set_hexpand: true,
+                    set_has_frame: false,
+                    set_icon_name: icon_names::EDIT_COPY,
+                    set_tooltip_text: Some("Copy to clipboard"),
+                },
+            },
+        }
+
+        let widgets = HistoryWidgets { label, copy_button };
+
+        (my_box, widgets)
+    }
+
+    fn bind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+        let HistoryWidgets { label, copy_button } = widgets;
+
+        label.set_markup(&format!(
+            "{}\n<small>{} · {}</small>",
+            glib::markup_escape_text(&self.entry.track),
+            glib::markup_escape_text(&self.entry.station),
+            self.entry.time(),
+        ));
+
+        let track = self.entry.track.clone();
+        self.handler = Some(copy_button.connect_clicked(move |button| {
+            button.clipboard().set_text(&track);
+        }));
+    }
+
+    fn unbind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+        // rows get recycled, so drop the old handler before the next bind
+        if let Some(handler) = self.handler.take() {
+            widgets.copy_button.disconnect(handler);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Filter {
+    query: String,
+    // HistoryEntry::key of the station picked in the dropdown
+    station: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    pub list_view_wrapper: TypedListView<HistoryItem, gtk::NoSelection>,
+    // backs the station filter dropdown, the first entry means every station
+    pub stations: gtk::StringList,
+    // the key of each station in the dropdown after the first
+    station_keys: Vec<String>,
+    entries: Vec<HistoryEntry>,
+    filter: Rc<RefCell<Filter>>,
+    limit: usize,
+    // entries changed since history.json was last written
+    dirty: bool,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        let filter = Rc::new(RefCell::new(Filter::default()));
+        let mut list_view_wrapper = TypedListView::new();
+        let filter_clone = Rc::clone(&filter);
+        list_view_wrapper.add_filter(move |item: &HistoryItem| {
+            let filter = filter_clone.borrow();
+            let query = filter.query.to_lowercase();
+            filter
+                .station
+                .as_ref()
+                .is_none_or(|station| station == item.entry.key())
+                && (item.entry.track.to_lowercase().contains(&query)
+                    || item.entry.station.to_lowercase().contains(&query))
+        });
+
+        Self {
+            list_view_wrapper,
+            stations: gtk::StringList::new(&["All stations"]),
+            station_keys: vec![],
+            entries: vec![],
+            filter,
+            limit,
+            dirty: false,
+        }
+    }
+
+    pub fn load(&mut self) {
+        self.entries = saver::load_history();
+        self.entries.truncate(self.limit);
+        self.refresh();
+    }
+
+    // Newest entries are kept at the front. Only the new row is added to the
+    // list, history.json is written by the next flush.
+    pub fn record(&mut self, station: &Station, now_playing: &NowPlaying) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as i64)
+            .unwrap_or_default();
+        let entry = HistoryEntry {
+            station: station.name.clone(),
+            uuid: station.uuid.clone(),
+            url: station.url.clone(),
+            track: now_playing.to_string(),
+            timestamp,
+        };
+        self.add_station(&entry);
+        self.list_view_wrapper.insert(
+            0,
+            HistoryItem {
+                entry: entry.clone(),
+                handler: None,
+            },
+        );
+        self.entries.insert(0, entry);
+        while self.entries.len() > self.limit {
+            self.entries.pop();
+            self.list_view_wrapper.remove(self.entries.len() as u32);
+        }
+        self.dirty = true;
+    }
+
+    // Write out whatever was recorded since last time
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            saver::save_history(&self.entries)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn set_query(&mut self, query: String) {
+        self.filter.borrow_mut().query = query;
+        self.list_view_wrapper.notify_filter_changed(0);
+    }
+
+    // position is the selected row of the station dropdown
+    pub fn set_station(&mut self, position: u32) {
+        self.filter.borrow_mut().station = match position {
+            0 => None,
+            _ => self.station_keys.get(position as usize - 1).cloned(),
+        };
+        self.list_view_wrapper.notify_filter_changed(0);
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        if self.entries.len() > limit {
+            self.entries.truncate(limit);
+            self.refresh();
+            self.dirty = true;
+        }
+    }
+
+    fn add_station(&mut self, entry: &HistoryEntry) {
+        if !self.station_keys.iter().any(|key| key == entry.key()) {
+            self.station_keys.push(entry.key().to_string());
+            self.stations.append(&entry.station);
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.list_view_wrapper.clear();
+        self.list_view_wrapper
+            .extend_from_iter(self.entries.iter().map(|entry| HistoryItem {
+                entry: entry.clone(),
+                handler: None,
+            }));
+
+        // keep "All stations" and swap out everything after it
+        self.station_keys.clear();
+        self.stations.splice(1, self.stations.n_items() - 1, &[]);
+        for entry in self.entries.clone() {
+            self.add_station(&entry);
+        }
+    }
+}
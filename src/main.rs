@@ -1,21 +1,15 @@
-// TODO
-// Show dialog when a stream fails
-//
-// mpris
-//
-// Figure out some way to speed up searches
-
 use gstreamer_audio::{StreamVolume, StreamVolumeFormat::*};
 use gstreamer_player::Player;
 use gtk::prelude::*;
 use radiobrowser::RadioBrowserAPI;
 use relm4::{
-    RelmObjectExt,
+    RelmObjectExt, adw,
     binding::StringBinding,
     gtk::{
         PolicyType,
         gdk::Rectangle,
-        glib::Propagation,
+        gio,
+        glib::{self, Propagation},
         pango,
     },
     prelude::*,
@@ -25,24 +19,73 @@ use relm4::{
     },
 };
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod icon_names {
     include!(concat!(env!("OUT_DIR"), "/icon_names.rs"));
 }
 
+mod alarm;
+mod duplicates;
+mod favicon;
+mod history;
+mod mirror;
+mod mpris;
+mod playlist;
+mod recorder;
+mod resolver;
 mod saver;
+mod scheduler;
 mod search;
+mod sleep;
 mod streamer;
+#[cfg(test)]
+mod test_util;
+mod undo;
 
 #[derive(Debug)]
 struct SearchItem {
     station: Station,
+    // already saved, so there is nothing to add
+    in_list: bool,
     sender: AsyncComponentSender<Radio>,
+    add_button: Option<gtk::Button>,
+    handler: Option<glib::SignalHandlerId>,
 }
 
 impl SearchItem {
-    fn new(station: Station, sender: AsyncComponentSender<Radio>) -> Self {
-        Self { station, sender }
+    fn new(station: Station, in_list: bool, sender: AsyncComponentSender<Radio>) -> Self {
+        Self {
+            station,
+            in_list,
+            sender,
+            add_button: None,
+            handler: None,
+        }
+    }
+
+    fn set_in_list(&mut self, in_list: bool) {
+        self.in_list = in_list;
+        self.refresh_button();
+    }
+
+    fn refresh_button(&self) {
+        let Some(add_button) = &self.add_button else {
+            return;
+        };
+        if self.in_list {
+            add_button.set_icon_name(icon_names::OBJECT_SELECT);
+            add_button.set_tooltip_text(Some("Already in your list"));
+        } else {
+            add_button.set_icon_name(icon_names::PLUS);
+            add_button.set_tooltip_text(Some("Add to your list"));
+        }
+        add_button.set_sensitive(!self.in_list);
     }
 }
 
@@ -86,18 +129,67 @@ impl RelmListItem for SearchItem {
         let station = self.station.clone();
         label.set_text(&self.station.name);
 
-        add_button.set_icon_name(icon_names::PLUS);
-        add_button.connect_clicked(move |_| {
-            sender.input(Msg::StationNameChanged(station.name.clone()));
-            sender.input(Msg::StationUrlChanged(station.url.clone()));
-            sender.input(Msg::AddStation);
-        });
+        self.add_button = Some(add_button.clone());
+        self.refresh_button();
+        self.handler = Some(add_button.connect_clicked(move |_| {
+            sender.input(Msg::AddSearchResult(station.clone()));
+        }));
+    }
+
+    fn unbind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+        if let Some(handler) = self.handler.take() {
+            widgets.add_button.disconnect(handler);
+        }
+        self.add_button = None;
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Error {
-    Error,
+    // A stream failed to play, domain and message come from the GStreamer error
+    Stream {
+        url: String,
+        domain: String,
+        message: String,
+    },
+    Search(String),
+    Storage(String),
+    Recording(String),
+    Playlist(String),
+    Resolve(String),
+    Mirror(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Stream {
+                url,
+                domain,
+                message,
+            } => write!(f, "Could not play {url}: {message} ({domain})"),
+            Error::Search(message) => write!(f, "Search failed: {message}"),
+            Error::Storage(message) => write!(f, "Storage error: {message}"),
+            Error::Recording(message) => write!(f, "Recording failed: {message}"),
+            Error::Playlist(message) => write!(f, "Playlist error: {message}"),
+            Error::Resolve(message) => write!(f, "Could not resolve station: {message}"),
+            Error::Mirror(message) => write!(f, "Offline station database: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -107,6 +199,11 @@ struct StationListItem {
     sender: AsyncComponentSender<Radio>,
     active: bool,
     labelbinding: StringBinding,
+    // added on bind and taken off again on unbind, rows get recycled
+    controllers: Vec<gtk::EventController>,
+    // the row this is bound to, so the summary and favicon can be updated
+    // without waiting for a rebind
+    widgets: Option<StationWidgets>,
 }
 
 impl StationListItem {
@@ -117,6 +214,8 @@ impl StationListItem {
             sender,
             active: false,
             labelbinding: StringBinding::new(station.name),
+            controllers: vec![],
+            widgets: None,
         }
     }
     pub fn active(&mut self) {
@@ -128,10 +227,36 @@ impl StationListItem {
         self.active = false;
         self.labelbinding.set_value(self.station.name.clone());
     }
+    pub fn set_station(&mut self, station: Station) {
+        self.station = station;
+        if self.active {
+            self.active();
+        } else {
+            self.inactive();
+        }
+        self.refresh_row();
+    }
+    fn refresh_row(&self) {
+        let Some(widgets) = &self.widgets else {
+            return;
+        };
+        let summary = self.station.summary();
+        widgets.summary.set_visible(!summary.is_empty());
+        widgets.summary.set_text(&summary);
+        match self.station.favicon.as_deref().and_then(favicon::cached) {
+            Some(path) => widgets.favicon.set_from_file(Some(path)),
+            None => widgets
+                .favicon
+                .set_icon_name(Some(icon_names::AUDIO_X_GENERIC)),
+        }
+    }
 }
 
+#[derive(Debug, Clone)]
 struct StationWidgets {
     label: gtk::Label,
+    summary: gtk::Label,
+    favicon: gtk::Image,
 }
 
 impl RelmListItem for StationListItem {
@@ -141,18 +266,41 @@ impl RelmListItem for StationListItem {
     fn setup(_item: &gtk::ListItem) -> (gtk::Box, StationWidgets) {
         relm4::view! {
             my_box = gtk::Box {
-                #[name = "label"]
-                gtk::Label,
+                set_spacing: 5,
+                #[name = "favicon"]
+                gtk::Image {
+                    set_pixel_size: 24,
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    #[name = "label"]
+                    gtk::Label {
+                        set_halign: gtk::Align::Start,
+                    },
+                    #[name = "summary"]
+                    gtk::Label {
+                        set_halign: gtk::Align::Start,
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        add_css_class: "dim-label",
+                        add_css_class: "caption",
+                    },
+                },
             },
         }
 
-        let widgets = StationWidgets { label };
+        let widgets = StationWidgets {
+            label,
+            summary,
+            favicon,
+        };
 
         (my_box, widgets)
     }
 
     fn bind(&mut self, widgets: &mut Self::Widgets, root: &mut Self::Root) {
-        let StationWidgets { label } = widgets;
+        self.widgets = Some(widgets.clone());
+        self.refresh_row();
+        let StationWidgets { label, .. } = widgets;
 
         let motion = gtk::EventControllerMotion::new();
 
@@ -171,15 +319,38 @@ impl RelmListItem for StationListItem {
         let click = gtk::GestureClick::new();
         click.set_button(0);
         let sender = self.sender.clone();
-        let station = self.station.clone();
         click.connect_pressed(move |controller, _, _, _| {
             if controller.current_button() == gtk::gdk::BUTTON_PRIMARY {
-                sender.input(Msg::Play(station.clone(), id));
+                // looked up on click so edits made since binding are picked up
+                sender.input(Msg::PlayId(id));
+            }
+        });
+
+        // drag a row onto another one to move it there
+        let drag = gtk::DragSource::new();
+        drag.set_actions(gtk::gdk::DragAction::MOVE);
+        drag.set_content(Some(&gtk::gdk::ContentProvider::for_value(
+            &(id as u64).to_value(),
+        )));
+        let drop = gtk::DropTarget::new(glib::Type::U64, gtk::gdk::DragAction::MOVE);
+        let sender = self.sender.clone();
+        drop.connect_drop(move |_, value, _, _| match value.get::<u64>() {
+            Ok(from) => {
+                sender.input(Msg::DropStation(from as usize, id));
+                true
             }
+            Err(_) => false,
         });
 
-        root.add_controller(motion);
-        root.add_controller(click);
+        self.controllers = vec![
+            motion.upcast(),
+            click.upcast(),
+            drag.upcast(),
+            drop.upcast(),
+        ];
+        for controller in &self.controllers {
+            root.add_controller(controller.clone());
+        }
 
         if self.active {
             self.active() // ensure we dont loose boldness when rebinding
@@ -188,30 +359,165 @@ impl RelmListItem for StationListItem {
         label.set_use_markup(true);
         label.add_binding(&self.labelbinding, "label");
     }
+
+    fn unbind(&mut self, _widgets: &mut Self::Widgets, root: &mut Self::Root) {
+        for controller in self.controllers.drain(..) {
+            root.remove_controller(&controller);
+        }
+        self.widgets = None;
+    }
+}
+
+#[derive(Debug, Default)]
+struct StationFilter {
+    group: Option<String>,
+    // lowercased already
+    query: String,
+}
+
+impl StationFilter {
+    fn matches(&self, station: &Station) -> bool {
+        self.group
+            .as_ref()
+            .is_none_or(|group| station.group.as_ref() == Some(group))
+            && (station.name.to_lowercase().contains(&self.query)
+                || station
+                    .tags
+                    .iter()
+                    .any(|tag| tag.to_lowercase().contains(&self.query))
+                || station
+                    .group
+                    .as_ref()
+                    .is_some_and(|group| group.to_lowercase().contains(&self.query)))
+    }
 }
 
 #[derive(Debug)]
 struct StationList {
-    list_view_wrapper: TypedListView<StationListItem, gtk::NoSelection>,
+    list_view_wrapper: TypedListView<StationListItem, gtk::SingleSelection>,
+    // station names in list order, for dropdowns that pick a station
+    names: gtk::StringList,
+    // backs the group sidebar, the first entry means every station
+    groups: gtk::StringList,
+    filter: Rc<RefCell<StationFilter>>,
+    // ids stay with a station for as long as it exists, whatever happens to
+    // the order, so they are never reused
+    next_id: usize,
     sender: AsyncComponentSender<Radio>,
 }
 
 impl StationList {
     fn new(sender: AsyncComponentSender<Radio>) -> Self {
+        let filter = Rc::new(RefCell::new(StationFilter::default()));
+        let mut list_view_wrapper = TypedListView::new();
+        let filter_clone = Rc::clone(&filter);
+        list_view_wrapper
+            .add_filter(move |item: &StationListItem| filter_clone.borrow().matches(&item.station));
+        // nothing is selected until a row is clicked or reached with the keyboard
+        list_view_wrapper.selection_model.set_autoselect(false);
+        list_view_wrapper.selection_model.set_can_unselect(true);
+
         Self {
-            list_view_wrapper: TypedListView::new(),
+            list_view_wrapper,
+            names: gtk::StringList::new(&[]),
+            groups: gtk::StringList::new(&["All stations"]),
+            filter,
+            next_id: 0,
             sender,
         }
     }
 
-    fn append(&mut self, station: Station) {
-        let id = self.list_view_wrapper.len();
-        self.list_view_wrapper.append(StationListItem::new(
-            station,
-            id as usize,
-            self.sender.clone(),
-        ));
+    fn append(&mut self, station: Station) -> usize {
+        self.insert(self.list_view_wrapper.len(), station)
+    }
+
+    fn insert(&mut self, position: u32, station: Station) -> usize {
+        let id = self.insert_unsaved(position, station);
+        self.save();
+        id
+    }
+
+    // for adding a batch of stations and saving once at the end
+    fn insert_unsaved(&mut self, position: u32, station: Station) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert_with_id(position, station, id);
+        id
+    }
+
+    // ids are never reused, so a station that is put back after being
+    // removed can have its old one again
+    fn insert_with_id(&mut self, position: u32, mut station: Station, id: usize) {
+        if station.key.is_none() {
+            station.key = Some(glib::uuid_string_random().to_string());
+        }
+        if let Some(url) = station.favicon.clone()
+            && favicon::cached(&url).is_none()
+        {
+            self.sender.oneshot_command(async move {
+                let path = favicon::fetch(&url).await;
+                CmdOut::FaviconFetched(url, path)
+            });
+        }
+        self.add_group(station.group.as_ref());
+        self.names.splice(position, 0, &[station.name.as_str()]);
+        self.list_view_wrapper.insert(
+            position,
+            StationListItem::new(station, id, self.sender.clone()),
+        );
+    }
+
+    fn position_of(&self, id: usize) -> Option<u32> {
+        (0..self.list_view_wrapper.len()).find(|x| {
+            self.list_view_wrapper
+                .get(*x)
+                .is_some_and(|item| item.borrow().id == id)
+        })
+    }
+
+    // Moves the station to position, keeping its id and whether it's playing.
+    // Returns where it was if it moved.
+    fn move_to(&mut self, id: usize, position: u32) -> Option<u32> {
+        let from = self.position_of(id)?;
+        let position = position.min(self.list_view_wrapper.len() - 1);
+        if from == position {
+            return None;
+        }
+        let item = self.list_view_wrapper.get(from)?;
+        let (station, active) = {
+            let item = item.borrow();
+            (item.station.clone(), item.active)
+        };
+        self.list_view_wrapper.remove(from);
+        self.names.remove(from);
+
+        let mut moved = StationListItem::new(station.clone(), id, self.sender.clone());
+        if active {
+            moved.active();
+        }
+        self.list_view_wrapper.insert(position, moved);
+        self.names.splice(position, 0, &[station.name.as_str()]);
         self.save();
+        Some(from)
+    }
+
+    fn selected_id(&self) -> Option<usize> {
+        let position = self.list_view_wrapper.selection_model.selected();
+        self.list_view_wrapper
+            .get_visible(position)
+            .map(|item| item.borrow().id)
+    }
+
+    // selection goes by visible position, so find where the filter put it
+    fn select(&self, id: usize) {
+        let position = (0..)
+            .map_while(|x| self.list_view_wrapper.get_visible(x))
+            .position(|item| item.borrow().id == id);
+        if let Some(position) = position {
+            self.list_view_wrapper
+                .selection_model
+                .set_selected(position as u32);
+        }
     }
 
     fn get_by_id(&self, id: Option<usize>) -> Option<TypedListItem<StationListItem>> {
@@ -233,71 +539,478 @@ impl StationList {
                 && item.borrow().id == id
             {
                 self.list_view_wrapper.remove(x);
+                self.names.remove(x);
             }
         }
+        // putting the station back with undo brings its group back too
+        self.prune_groups();
         self.save();
     }
 
-    fn save(&self) {
+    fn update_by_id(&mut self, id: usize, station: Station) {
+        self.add_group(station.group.as_ref());
+        for x in 0..self.list_view_wrapper.len() {
+            if let Some(item) = self.list_view_wrapper.get(x)
+                && item.borrow().id == id
+            {
+                self.names.splice(x, 1, &[station.name.as_str()]);
+                item.borrow_mut().set_station(station.clone());
+            }
+        }
+        self.prune_groups();
+        // the station may not match the filter any more
+        self.list_view_wrapper.notify_filter_changed(0);
+        self.save();
+    }
+
+    // position is the selected row of the group sidebar
+    fn set_group_filter(&mut self, position: u32) {
+        self.filter.borrow_mut().group = match position {
+            0 => None,
+            _ => self.groups.string(position).map(|group| group.to_string()),
+        };
+        self.list_view_wrapper.notify_filter_changed(0);
+    }
+
+    fn set_query(&mut self, query: &str) {
+        self.filter.borrow_mut().query = query.to_lowercase();
+        self.list_view_wrapper.notify_filter_changed(0);
+    }
+
+    // Position of the nearest station in the direction of offset that the
+    // filter lets through, so moving never hops over hidden stations
+    // without anything visibly happening
+    fn visible_neighbour(&self, id: usize, offset: i32) -> Option<u32> {
+        let mut position = self.position_of(id)?;
+        loop {
+            position = position.checked_add_signed(offset)?;
+            let item = self.list_view_wrapper.get(position)?;
+            if self.filter.borrow().matches(&item.borrow().station) {
+                return Some(position);
+            }
+        }
+    }
+
+    fn add_group(&mut self, group: Option<&String>) {
+        if let Some(group) = group
+            && !self.group_names().contains(group)
+        {
+            self.groups.append(group);
+        }
+    }
+
+    fn group_names(&self) -> Vec<String> {
+        (1..self.groups.n_items())
+            .filter_map(|position| self.groups.string(position))
+            .map(|group| group.to_string())
+            .collect()
+    }
+
+    // groups only exist while there is something in them
+    fn prune_groups(&mut self) {
+        let stations = self.stations();
+        for position in (1..self.groups.n_items()).rev() {
+            if let Some(group) = self.groups.string(position)
+                && !stations
+                    .iter()
+                    .any(|station| station.group.as_deref() == Some(group.as_str()))
+            {
+                self.groups.remove(position);
+            }
+        }
+    }
+
+    // show a favicon that has just been downloaded on every row using it
+    fn refresh_favicon(&self, url: &str) {
+        for x in 0..self.list_view_wrapper.len() {
+            if let Some(item) = self.list_view_wrapper.get(x)
+                && item.borrow().station.favicon.as_deref() == Some(url)
+            {
+                item.borrow().refresh_row();
+            }
+        }
+    }
+
+    // the station a schedule or alarm was saved with
+    fn find_station(&self, saved: &Station) -> Option<usize> {
+        for x in 0..self.list_view_wrapper.len() {
+            if let Some(item) = self.list_view_wrapper.get(x)
+                && scheduler::same_station(saved, &item.borrow().station)
+            {
+                return Some(item.borrow().id);
+            }
+        }
+        None
+    }
+
+    fn find_duplicate(&self, station: &Station) -> Option<usize> {
+        for x in 0..self.list_view_wrapper.len() {
+            if let Some(item) = self.list_view_wrapper.get(x)
+                && duplicates::is_duplicate(&item.borrow().station, station)
+            {
+                return Some(item.borrow().id);
+            }
+        }
+        None
+    }
+
+    // Folds every station into the first one it duplicates, returns the
+    // removed stations and their ids along with the ids they went into, and
+    // the changes made for undo
+    fn merge_duplicates(&mut self) -> (Vec<(usize, Station, usize)>, Vec<undo::Change>) {
+        let mut merged = vec![];
+        let mut changes = vec![];
+        let mut x = 0;
+        while let Some(item) = self.list_view_wrapper.get(x) {
+            let (station, id) = {
+                let item = item.borrow();
+                (item.station.clone(), item.id)
+            };
+            let original = (0..x)
+                .filter_map(|y| self.list_view_wrapper.get(y))
+                .find(|earlier| duplicates::is_duplicate(&earlier.borrow().station, &station));
+            match original {
+                Some(original) => {
+                    let before = original.borrow().station.clone();
+                    let mut kept = before.clone();
+                    duplicates::merge(&mut kept, station.clone());
+                    original.borrow_mut().set_station(kept);
+                    let kept_id = original.borrow().id;
+                    merged.push((id, station.clone(), kept_id));
+                    changes.push(undo::Change::Edited {
+                        id: kept_id,
+                        before,
+                    });
+                    changes.push(undo::Change::Removed {
+                        id,
+                        station,
+                        position: x,
+                    });
+                    self.list_view_wrapper.remove(x);
+                    self.names.remove(x);
+                }
+                None => x += 1,
+            }
+        }
+        if !merged.is_empty() {
+            self.prune_groups();
+            self.save();
+        }
+        (merged, changes)
+    }
+
+    fn stations(&self) -> Vec<Station> {
         let mut stations = vec![];
         for x in 0..self.list_view_wrapper.len() {
             if let Some(item) = self.list_view_wrapper.get(x) {
                 stations.push(item.borrow().station.clone());
             }
         }
-        saver::save_stations(stations).unwrap();
+        stations
+    }
+
+    fn save(&self) {
+        if let Err(error) = saver::save_stations(&self.stations(), &self.group_names()) {
+            self.sender.input(Msg::ShowError(error));
+        }
     }
 
     fn load(&mut self) {
-        let stations = saver::load_stations();
-        let _: Vec<_> = stations
-            .iter()
-            .map(|station| self.append(station.clone()))
-            .collect();
+        let (data, error) = saver::load_stations();
+        if let Some(error) = error {
+            self.sender.input(Msg::ShowError(error));
+        }
+        // any group missing from the list gets added after these as its
+        // stations are appended
+        for group in &data.groups {
+            self.groups.append(group);
+        }
+        let keyless = data.stations.iter().any(|station| station.key.is_none());
+        for station in data.stations {
+            self.insert_unsaved(self.list_view_wrapper.len(), station);
+        }
+        // only write back what came from an older version or the backup, or
+        // picked up groups or keys the file didn't have
+        if data.stale || keyless || self.group_names() != data.groups {
+            self.save();
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Station {
     name: String,
     url: String, // https://www.radio-browser.info/
+    // what was typed in when adding, if url had to be resolved from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original_url: Option<String>,
+    // the urls of stations merged into this one, so adding them again is
+    // still caught as a duplicate
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    // given when the station is added and never changed, so schedules and
+    // alarms can find it again whatever happens to its url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    // everything from here on is filled in from radio-browser when the
+    // station is added from a search
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    codec: Option<String>,
+    // kbps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    favicon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+}
+
+impl Station {
+    // the line under the name in the station list
+    fn summary(&self) -> String {
+        let mut parts: Vec<String> = self.tags.iter().take(3).cloned().collect();
+        parts.extend(self.codec.clone());
+        parts.extend(self.bitrate.map(|bitrate| format!("{bitrate} kbps")));
+        parts.join(" · ")
+    }
+
+    // everything we know about the station, for the details popover
+    fn details(&self) -> String {
+        let mut lines = vec![self.url.clone()];
+        if let Some(group) = &self.group {
+            lines.push(format!("Group: {group}"));
+        }
+        if !self.tags.is_empty() {
+            lines.push(format!("Tags: {}", self.tags.join(", ")));
+        }
+        for (label, value) in [
+            ("Country", &self.country),
+            ("Language", &self.language),
+            ("Codec", &self.codec),
+        ] {
+            if let Some(value) = value {
+                lines.push(format!("{label}: {value}"));
+            }
+        }
+        if let Some(bitrate) = self.bitrate {
+            lines.push(format!("Bitrate: {bitrate} kbps"));
+        }
+        if let Some(notes) = &self.notes {
+            lines.push(String::new());
+            lines.push(notes.clone());
+        }
+        lines.join("\n")
+    }
 }
 
 #[derive(Debug)]
 struct Radio {
     station_list: StationList,
     ctx_menu_handle: gtk::Popover,
+    toast_overlay_handle: adw::ToastOverlay,
     search_results_handle: TypedListView<SearchItem, gtk::NoSelection>,
     title: String,
     new_station_name: String,
     new_station_url: String,
     hover_id: Option<usize>,
     menu_id: usize,
+    // where the right click menu was opened, the edit popover opens there too
+    menu_rect: Rectangle,
+    edit_popover_handle: gtk::Popover,
+    edit_name_buffer: gtk::EntryBuffer,
+    edit_url_buffer: gtk::EntryBuffer,
+    edit_notes_buffer: gtk::TextBuffer,
+    group_buffer: gtk::EntryBuffer,
+    details_popover_handle: gtk::Popover,
+    // the station the details popover is showing
+    details: Option<Station>,
     playing_id: Option<usize>,
     player: Player,
     volume: f64,
-    radio_browser_api: RadioBrowserAPI,
+    // None until the first search manages to reach radio-browser
+    radio_browser_api: Option<RadioBrowserAPI>,
+    // the last search couldn't find a radio-browser server
+    search_offline: bool,
     query: String,
+    // bumped for every new search so results of older ones get dropped
+    search_generation: u64,
+    // the request for the page being fetched, dropped when a new search
+    // starts so it doesn't hold up the connection
+    search_task: Option<relm4::JoinHandle<()>>,
+    search_loading: bool,
+    // the last page came back short, there is nothing more to fetch
+    search_exhausted: bool,
+    // shown above the results instead of an empty list
+    search_status: Option<String>,
+    // bumped on every keystroke, only the search-as-you-type timeout started
+    // last gets to search
+    search_debounce: u64,
+    // same again for saving settings that are typed in, with
+    // whether there is still something left to save
+    settings_debounce: u64,
+    settings_dirty: bool,
+    search_cache: search::Cache,
+    mirror: Option<Arc<mirror::Mirror>>,
+    // downloading or reading the station database
+    mirror_busy: bool,
+    // unix time the station database was last refreshed or tried to be, so
+    // a failing download isn't retried on every tick
+    mirror_checked: i64,
     volume_icon: String,
     muted_volume: f64,
     muted: bool,
+    mpris: Option<mpris::Mpris>,
+    now_playing: Option<streamer::NowPlaying>,
+    history: history::History,
+    settings: saver::Settings,
+    recorder: Option<recorder::Recorder>,
+    // where recordings get their data from the player
+    tap: recorder::Tap,
+    schedules: Vec<scheduler::Schedule>,
+    schedule_list: TypedListView<scheduler::RuleItem, gtk::NoSelection>,
+    // index into schedules of the one that is currently running
+    active_schedule: Option<usize>,
+    // station id the running schedule started, so it only stops its own
+    scheduled_id: Option<usize>,
+    new_schedule_station: u32,
+    new_schedule_action: scheduler::Action,
+    new_schedule_weekdays: [bool; 7],
+    new_schedule_start: String,
+    new_schedule_end: String,
+    sleep_timer: sleep::SleepTimer,
+    sleep_source: Option<glib::SourceId>,
+    alarms: Vec<alarm::Alarm>,
+    alarm_list: TypedListView<scheduler::RuleItem, gtk::NoSelection>,
+    new_alarm_station: u32,
+    new_alarm_weekdays: [bool; 7],
+    new_alarm_time: String,
+    alarm_ringing: bool,
+    alarm_station: Option<Station>,
+    // the volume the alarm fades in to
+    alarm_volume: f64,
+    // the volume and whether it was muted, to go back to once it's over
+    alarm_restore: Option<(f64, bool)>,
+    alarm_fade_source: Option<glib::SourceId>,
+    alarm_tone: Option<alarm::Tone>,
+    // an alarm only goes off once in the minute it is set for, this is the
+    // unix time (in minutes) it last went off
+    last_alarm_minute: Option<i64>,
+    // snoozed station and the unix time it should go off again
+    snoozed: Option<(Station, i64)>,
+    // native dialogs have to be kept alive until they are answered
+    file_chooser: Option<gtk::FileChooserNative>,
+    undo_stack: undo::UndoStack,
+    // the toast offering to undo a delete, it goes away once something else
+    // has been done since undo would no longer bring back the same thing
+    undo_toast: Option<adw::Toast>,
 }
 
 #[derive(Debug)]
 enum Msg {
     Play(Station, usize),
+    PlayId(usize),
     Stop,
     ChangeVolume(f64),
     VolumeChanged(f64),
     StationNameChanged(String),
     StationUrlChanged(String),
     AddStation,
+    AddSearchResult(Station),
     ShowMenu(f64, f64),
     DeleteStation,
+    EditStation,
+    SaveStation,
+    ShowDetails,
+    MoveStationUp,
+    MoveStationDown,
+    DropStation(usize, usize),
+    MergeDuplicates,
+    Undo,
+    SetGroup,
+    GroupSelected(u32),
+    StationFilterChanged(String),
     SetHoverId(Option<usize>),
     SearchQueryChanged(String),
+    SearchFilterChanged(search::Filter),
     Search,
+    DebouncedSearch(u64),
+    DebouncedSaveSettings(u64),
+    LoadMoreResults,
+    OfflineSearchChanged(bool),
+    DownloadMirror,
+    LoadMirror,
+    // and whether to keep it as the offline copy from now on
+    LoadMirrorFile(PathBuf, bool),
+    ApiServersChanged(String),
     ToggleMute,
+    StreamError(Error),
+    ShowError(Error),
+    NowPlaying(Option<streamer::NowPlaying>),
+    HistoryQueryChanged(String),
+    HistoryStationChanged(u32),
+    HistoryLimitChanged(usize),
+    ToggleRecording,
+    RecordingError(Error),
+    SplitRecordingsChanged(bool),
+    SchedulerTick,
+    ScheduleStationChanged(u32),
+    ScheduleActionChanged(u32),
+    ScheduleWeekdayToggled(usize, bool),
+    ScheduleStartChanged(String),
+    ScheduleEndChanged(String),
+    AddSchedule,
+    DeleteSchedule(usize),
+    StartSleepTimer(u32),
+    CancelSleepTimer,
+    SleepTick,
+    AlarmStationChanged(u32),
+    AlarmWeekdayToggled(usize, bool),
+    AlarmTimeChanged(String),
+    AddAlarm,
+    DeleteAlarm(usize),
+    AlarmFadeTick,
+    AlarmFallback,
+    SnoozeAlarm,
+    StopAlarm,
+    ImportPlaylist,
+    ExportPlaylist,
+    ImportPlaylistFile(PathBuf),
+    ExportPlaylistFile(PathBuf),
+}
+
+#[derive(Debug)]
+enum CmdOut {
+    StationResolved(Station, Result<String, Error>),
+    // an imported playlist, each station with what its url resolved to
+    PlaylistResolved {
+        stations: Vec<(Station, Result<String, Error>)>,
+        // already in the list before resolving
+        skipped: usize,
+    },
+    FaviconFetched(String, Option<PathBuf>),
+    // None when there is no copy of the station database yet
+    MirrorLoaded(Result<Option<mirror::Mirror>, Error>),
+    SearchResults {
+        // the search generation the results are for
+        generation: u64,
+        // where they go in the search cache, offline results aren't cached
+        key: Option<String>,
+        // the connection made for the search, so the next one can reuse it
+        api: Option<RadioBrowserAPI>,
+        results: Result<Vec<Station>, Error>,
+    },
 }
 
 #[relm4::component(async)]
@@ -305,161 +1018,813 @@ impl AsyncComponent for Radio {
     type Init = ();
     type Input = Msg;
     type Output = ();
-    type CommandOutput = ();
+    type CommandOutput = CmdOut;
 
     view! {
         gtk::Window {
             #[watch]
             set_title: Some(&model.title),
-            gtk::Box {
-                set_orientation: gtk::Orientation::Vertical,
-                set_spacing: 5,
-                set_margin_all: 5,
-
-                // using a center box so I can maybe have something in the
-                // middle in the future
-                gtk::CenterBox {
-                    #[wrap(Some)]
-                    set_start_widget = &gtk::Box {
-                        set_spacing: 2,
-                        set_orientation: gtk::Orientation::Horizontal,
-                        // Stop button
-                        gtk::Button {
-                            set_has_frame: false,
-                            set_icon_name: icon_names::STOP_LARGE,
-                            connect_clicked => Msg::Stop,
+            // keep running in the background while there is something scheduled
+            // or an alarm set
+            #[watch]
+            set_hide_on_close: !model.schedules.is_empty() || !model.alarms.is_empty(),
+            #[local_ref]
+            toast_overlay -> adw::ToastOverlay {
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+
+                    // stream title of whatever is playing sits in the middle
+                    gtk::CenterBox {
+                        #[wrap(Some)]
+                        set_start_widget = &gtk::Box {
+                            set_spacing: 2,
+                            set_orientation: gtk::Orientation::Horizontal,
+                            // Stop button
+                            gtk::Button {
+                                set_has_frame: false,
+                                set_icon_name: icon_names::STOP_LARGE,
+                                connect_clicked => Msg::Stop,
+                            },
+                            // Record button
+                            gtk::ToggleButton {
+                                set_has_frame: false,
+                                set_icon_name: icon_names::MEDIA_RECORD,
+                                set_tooltip_text: Some("Record"),
+                                #[watch]
+                                set_sensitive: model.playing_id.is_some(),
+                                #[watch]
+                                set_active: model.recorder.is_some(),
+                                connect_clicked => Msg::ToggleRecording,
+                            },
+                            // mute button
+                            gtk::Button {
+                                set_has_frame: false,
+                                #[watch]
+                                set_icon_name: &model.volume_icon,
+                                connect_clicked => Msg::ToggleMute,
+                            },
+                            gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, 1.0, 0.1){
+                                set_width_request: 120,
+                                connect_change_value[sender] => move |_, _, val| {
+                                    sender.input(Msg::ChangeVolume(val));
+                                    Propagation::Proceed
+                                },
+                                #[watch]
+                                set_value: model.volume,
+                            },
+                            // Sleep timer
+                            gtk::MenuButton {
+                                set_has_frame: false,
+                                set_icon_name: icon_names::MOON,
+                                set_tooltip_text: Some("Sleep timer"),
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: sleep_popover = &gtk::Popover {
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::Button {
+                                            set_label: "15 minutes",
+                                            connect_clicked => Msg::StartSleepTimer(15),
+                                        },
+                                        gtk::Button {
+                                            set_label: "30 minutes",
+                                            connect_clicked => Msg::StartSleepTimer(30),
+                                        },
+                                        gtk::Button {
+                                            set_label: "60 minutes",
+                                            connect_clicked => Msg::StartSleepTimer(60),
+                                        },
+                                        gtk::Separator {},
+                                        gtk::Box {
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            set_spacing: 5,
+                                            #[name = "sleep_minutes"]
+                                            gtk::SpinButton::with_range(1.0, 600.0, 5.0) {
+                                                set_value: 45.0,
+                                            },
+                                            gtk::Button {
+                                                set_label: "Start",
+                                                connect_clicked[sender, sleep_minutes] => move |_| {
+                                                    sender.input(Msg::StartSleepTimer(sleep_minutes.value() as u32));
+                                                },
+                                            },
+                                        },
+                                    },
+                                },
+                            },
+                            gtk::Label {
+                                #[watch]
+                                set_visible: model.sleep_timer.remaining.is_some(),
+                                #[watch]
+                                set_label: &model
+                                    .sleep_timer
+                                    .remaining
+                                    .map(|seconds| format!("{}:{:02}", seconds / 60, seconds % 60))
+                                    .unwrap_or_default(),
+                            },
+                            gtk::Button {
+                                set_has_frame: false,
+                                set_icon_name: icon_names::CROSS,
+                                set_tooltip_text: Some("Cancel sleep timer"),
+                                #[watch]
+                                set_visible: model.sleep_timer.remaining.is_some(),
+                                connect_clicked => Msg::CancelSleepTimer,
+                            },
                         },
-                        // mute button
-                        gtk::Button {
-                            set_has_frame: false,
+
+                        #[wrap(Some)]
+                        set_center_widget = &gtk::Label {
+                            set_ellipsize: pango::EllipsizeMode::End,
                             #[watch]
-                            set_icon_name: &model.volume_icon,
-                            connect_clicked => Msg::ToggleMute,
-                        },
-                        gtk::Scale::with_range(gtk::Orientation::Horizontal, 0.0, 1.0, 0.1){
-                            set_width_request: 120,
-                            connect_change_value[sender] => move |_, _, val| {
-                                sender.input(Msg::ChangeVolume(val));
-                                Propagation::Proceed
-                            },
+                            set_label: &model
+                                .now_playing
+                                .as_ref()
+                                .map(|now_playing| now_playing.to_string())
+                                .unwrap_or_default(),
                             #[watch]
-                            set_value: model.volume,
+                            set_tooltip_text: model
+                                .now_playing
+                                .as_ref()
+                                .map(|now_playing| now_playing.to_string())
+                                .as_deref(),
                         },
-                    },
 
-                    #[wrap(Some)]
-                    set_end_widget = &gtk::Box{
-                        set_halign: gtk::Align::End,
-                        set_spacing: 5,
-                        // Search button
-                        gtk::MenuButton {
-                            set_icon_name: icon_names::SEARCH_GLOBAL,
-                            set_direction: gtk::ArrowType::Down,
-                            #[wrap(Some)]
-                            set_popover: search_popover = &gtk::Popover{
-                                gtk::Box {
-                                    set_orientation: gtk::Orientation::Vertical,
+                        #[wrap(Some)]
+                        set_end_widget = &gtk::Box{
+                            set_halign: gtk::Align::End,
+                            set_spacing: 5,
+                            // History button
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::CLOCK,
+                                set_tooltip_text: Some("Song history"),
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: history_popover = &gtk::Popover {
                                     gtk::Box {
-                                        set_spacing: 2,
-                                        set_margin_bottom: 5,
-                                        set_margin_end: 2,
-                                        set_margin_start: 2,
-                                        set_orientation: gtk::Orientation::Horizontal,
-                                        gtk::SearchEntry {
-                                            set_hexpand: true,
-                                            connect_changed[sender] => move |entry| {
-                                                let query = entry.text();
-                                                sender.input(Msg::SearchQueryChanged(query.into()));
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::Box {
+                                            set_spacing: 2,
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            gtk::SearchEntry {
+                                                set_hexpand: true,
+                                                connect_search_changed[sender] => move |entry| {
+                                                    sender.input(Msg::HistoryQueryChanged(entry.text().into()));
+                                                },
+                                            },
+                                            gtk::DropDown {
+                                                set_model: Some(&model.history.stations),
+                                                connect_selected_notify[sender] => move |dropdown| {
+                                                    sender.input(Msg::HistoryStationChanged(dropdown.selected()));
+                                                },
+                                            },
+                                        },
+
+                                        gtk::ScrolledWindow {
+                                            set_height_request: 250,
+                                            set_width_request: 350,
+                                            set_hscrollbar_policy: PolicyType::Never,
+                                            #[local_ref]
+                                            history_list -> gtk::ListView {
+                                            }
+                                        },
+
+                                        gtk::Box {
+                                            set_spacing: 5,
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            gtk::Label {
+                                                set_label: "Tracks to keep:",
                                             },
-                                            connect_activate[sender] => move |_| {
-                                                sender.input(Msg::Search)
+                                            gtk::SpinButton::with_range(10.0, 10000.0, 10.0) {
+                                                set_value: model.settings.history_limit as f64,
+                                                connect_value_changed[sender] => move |spin| {
+                                                    sender.input(Msg::HistoryLimitChanged(spin.value() as usize));
+                                                },
+                                            },
+                                        },
+                                    }
+                                }
+                            },
+                            // Snooze, only there while an alarm is going off
+                            gtk::Button {
+                                set_label: "Snooze",
+                                #[watch]
+                                set_visible: model.alarm_ringing,
+                                connect_clicked => Msg::SnoozeAlarm,
+                            },
+                            // Alarm button
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::ALARM,
+                                set_tooltip_text: Some("Alarms"),
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: alarm_popover = &gtk::Popover {
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::ScrolledWindow {
+                                            set_height_request: 150,
+                                            set_width_request: 300,
+                                            set_hscrollbar_policy: PolicyType::Never,
+                                            #[local_ref]
+                                            alarm_list_view -> gtk::ListView {
                                             }
                                         },
+                                        gtk::Separator {},
+                                        gtk::DropDown {
+                                            set_model: Some(&model.station_list.names),
+                                            connect_selected_notify[sender] => move |dropdown| {
+                                                sender.input(Msg::AlarmStationChanged(dropdown.selected()));
+                                            },
+                                        },
+                                        append: &scheduler::weekday_toggles(&sender, Msg::AlarmWeekdayToggled),
+                                        gtk::Box {
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            set_spacing: 5,
+                                            gtk::Label {
+                                                set_label: "At",
+                                            },
+                                            gtk::Entry {
+                                                set_placeholder_text: Some("07:00"),
+                                                set_width_chars: 5,
+                                                connect_changed[sender] => move |entry| {
+                                                    sender.input(Msg::AlarmTimeChanged(entry.text().into()));
+                                                }
+                                            },
+                                        },
                                         gtk::Button {
-                                            set_icon_name: icon_names::LOUPE,
-                                            connect_clicked => Msg::Search,
+                                            set_label: "Add Alarm",
+                                            connect_clicked => Msg::AddAlarm,
                                         },
-                                    },
-
-                                    gtk::ScrolledWindow {
-                                        set_height_request: 200,
-                                        set_hscrollbar_policy: PolicyType::Never,
-                                        #[local_ref]
-                                        search_results -> gtk::ListView {
-                                        }
                                     }
                                 }
-                            }
-                        },
-                        // Add button
-                        gtk::MenuButton {
-                            set_icon_name: icon_names::PLUS,
-                            set_direction: gtk::ArrowType::Down,
-                            #[wrap(Some)]
-                            set_popover: popover = &gtk::Popover {
-                                set_position: gtk::PositionType::Right,
-
-                                gtk::Box {
-                                    set_orientation: gtk::Orientation::Vertical,
-                                    set_spacing: 5,
-                                    gtk::Label {
-                                        set_halign: gtk::Align::Start,
-                                        set_label: "Name:",
-                                    },
-                                    gtk::Entry {
-                                        connect_changed[sender] => move |entry| {
-                                            let buffer = entry.buffer();
-                                            sender.input(Msg::StationNameChanged(buffer.text().into()));
-                                        }
-                                    },
-                                    gtk::Label {
-                                        set_halign: gtk::Align::Start,
-                                        set_label: "URL:",
-                                    },
-                                    gtk::Entry {
-                                        connect_changed[sender] => move |entry| {
-                                            let buffer = entry.buffer();
-                                            sender.input(Msg::StationUrlChanged(buffer.text().into()));
-                                        }
-                                    },
-                                    gtk::Separator {},
-                                    gtk::Button {
-                                        set_label: "Add Station",
-                                        connect_clicked => Msg::AddStation,
-                                    },
-
-                                },
                             },
-                        },
-                    },
-                },
-
-                #[local_ref]
-                // right click menu
-                ctx_menu -> gtk::Popover {
-                    gtk::Button {
-                        set_label: "Delete Station",
-                        connect_clicked => Msg::DeleteStation,
-                    },
-                },
+                            // Schedule button
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::CALENDAR,
+                                set_tooltip_text: Some("Schedules"),
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: schedule_popover = &gtk::Popover {
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::ScrolledWindow {
+                                            set_height_request: 150,
+                                            set_width_request: 300,
+                                            set_hscrollbar_policy: PolicyType::Never,
+                                            #[local_ref]
+                                            schedule_list_view -> gtk::ListView {
+                                            }
+                                        },
+                                        gtk::Separator {},
+                                        gtk::DropDown {
+                                            set_model: Some(&model.station_list.names),
+                                            connect_selected_notify[sender] => move |dropdown| {
+                                                sender.input(Msg::ScheduleStationChanged(dropdown.selected()));
+                                            },
+                                        },
+                                        gtk::DropDown::from_strings(&["Play", "Record"]) {
+                                            connect_selected_notify[sender] => move |dropdown| {
+                                                sender.input(Msg::ScheduleActionChanged(dropdown.selected()));
+                                            },
+                                        },
+                                        append: &scheduler::weekday_toggles(&sender, Msg::ScheduleWeekdayToggled),
+                                        gtk::Box {
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            set_spacing: 5,
+                                            gtk::Label {
+                                                set_label: "From",
+                                            },
+                                            gtk::Entry {
+                                                set_placeholder_text: Some("08:00"),
+                                                set_width_chars: 5,
+                                                connect_changed[sender] => move |entry| {
+                                                    sender.input(Msg::ScheduleStartChanged(entry.text().into()));
+                                                }
+                                            },
+                                            gtk::Label {
+                                                set_label: "to",
+                                            },
+                                            gtk::Entry {
+                                                set_placeholder_text: Some("09:00"),
+                                                set_width_chars: 5,
+                                                connect_changed[sender] => move |entry| {
+                                                    sender.input(Msg::ScheduleEndChanged(entry.text().into()));
+                                                }
+                                            },
+                                        },
+                                        gtk::Button {
+                                            set_label: "Add Schedule",
+                                            connect_clicked => Msg::AddSchedule,
+                                        },
+                                    }
+                                }
+                            },
+                            // Search button
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::SEARCH_GLOBAL,
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: search_popover = &gtk::Popover{
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        gtk::Box {
+                                            set_spacing: 2,
+                                            set_margin_bottom: 5,
+                                            set_margin_end: 2,
+                                            set_margin_start: 2,
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            gtk::SearchEntry {
+                                                set_hexpand: true,
+                                                connect_changed[sender] => move |entry| {
+                                                    let query = entry.text();
+                                                    sender.input(Msg::SearchQueryChanged(query.into()));
+                                                },
+                                                connect_activate[sender] => move |_| {
+                                                    sender.input(Msg::Search)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_icon_name: icon_names::LOUPE,
+                                                connect_clicked => Msg::Search,
+                                            },
+                                        },
 
-                // station list
-                gtk::ScrolledWindow {
-                    set_vexpand: true,
+                                        gtk::Expander {
+                                            set_margin_bottom: 5,
+                                            #[watch]
+                                            set_label: Some(if model.settings.search_filters.is_empty() {
+                                                "Filters"
+                                            } else {
+                                                "Filters (in use)"
+                                            }),
+                                            #[wrap(Some)]
+                                            set_child = &gtk::Grid {
+                                                set_row_spacing: 5,
+                                                set_column_spacing: 5,
+                                                attach[0, 0, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Tag",
+                                                },
+                                                attach[1, 0, 1, 1] = &gtk::Entry {
+                                                    set_hexpand: true,
+                                                    set_placeholder_text: Some("jazz"),
+                                                    set_text: &model.settings.search_filters.tag,
+                                                    connect_changed[sender] => move |entry| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::Tag(entry.text().into()),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 1, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Country",
+                                                },
+                                                attach[1, 1, 1, 1] = &gtk::Entry {
+                                                    set_hexpand: true,
+                                                    set_placeholder_text: Some("Germany"),
+                                                    set_text: &model.settings.search_filters.country,
+                                                    connect_changed[sender] => move |entry| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::Country(entry.text().into()),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 2, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Country code",
+                                                },
+                                                attach[1, 2, 1, 1] = &gtk::Entry {
+                                                    set_hexpand: true,
+                                                    set_placeholder_text: Some("DE"),
+                                                    set_text: &model.settings.search_filters.countrycode,
+                                                    connect_changed[sender] => move |entry| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::Countrycode(entry.text().into()),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 3, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Language",
+                                                },
+                                                attach[1, 3, 1, 1] = &gtk::Entry {
+                                                    set_hexpand: true,
+                                                    set_placeholder_text: Some("german"),
+                                                    set_text: &model.settings.search_filters.language,
+                                                    connect_changed[sender] => move |entry| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::Language(entry.text().into()),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 4, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Codec",
+                                                },
+                                                attach[1, 4, 1, 1] = &gtk::Entry {
+                                                    set_hexpand: true,
+                                                    set_placeholder_text: Some("MP3"),
+                                                    set_text: &model.settings.search_filters.codec,
+                                                    connect_changed[sender] => move |entry| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::Codec(entry.text().into()),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 5, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Minimum bitrate",
+                                                },
+                                                attach[1, 5, 1, 1] = &gtk::SpinButton::with_range(0.0, 1024.0, 32.0) {
+                                                    set_value: model.settings.search_filters.min_bitrate as f64,
+                                                    connect_value_changed[sender] => move |spin| {
+                                                        sender.input(Msg::SearchFilterChanged(
+                                                            search::Filter::MinBitrate(spin.value() as u16),
+                                                        ));
+                                                    },
+                                                },
+                                                attach[0, 6, 1, 1] = &gtk::Label {
+                                                    set_halign: gtk::Align::Start,
+                                                    set_label: "Order by",
+                                                },
+                                                attach[1, 6, 1, 1] = &gtk::DropDown::from_strings(&search::Order::LABELS) {
+                                                    set_selected: model.settings.search_filters.order.position(),
+                                                    connect_selected_notify[sender] => move |dropdown| {
+                                                        sender.input(Msg::SearchFilterChanged(search::Filter::Order(
+                                                            search::Order::from_position(dropdown.selected()),
+                                                        )));
+                                                    },
+                                                },
+                                            },
+                                        },
 
-                    #[local_ref]
-                    station_list_view -> gtk::ListView {
-                        add_controller = gtk::GestureClick {
-                            set_button: 0,
-                            connect_pressed[sender] => move |controller, _, x, y| {
-                                if controller.current_button() == gtk::gdk::BUTTON_SECONDARY {
-                                    sender.input(Msg::ShowMenu(x, y));
-                                }
-                            }
+                                        gtk::Box {
+                                            set_spacing: 5,
+                                            set_halign: gtk::Align::Center,
+                                            #[watch]
+                                            set_visible: model.search_offline && !model.search_loading,
+                                            gtk::Image {
+                                                set_icon_name: Some(icon_names::OFFLINE_GLOBE),
+                                            },
+                                            gtk::Label {
+                                                set_label: "Offline, your saved stations still play",
+                                            },
+                                            gtk::Button {
+                                                set_label: "Try Again",
+                                                connect_clicked => Msg::Search,
+                                            },
+                                        },
+
+                                        gtk::Box {
+                                            set_spacing: 5,
+                                            set_halign: gtk::Align::Center,
+                                            #[watch]
+                                            set_visible: model.search_loading || model.search_status.is_some(),
+                                            gtk::Spinner {
+                                                #[watch]
+                                                set_visible: model.search_loading,
+                                                #[watch]
+                                                set_spinning: model.search_loading,
+                                            },
+                                            gtk::Label {
+                                                set_wrap: true,
+                                                #[watch]
+                                                set_label: model.search_status.as_deref().unwrap_or_default(),
+                                            },
+                                        },
+
+                                        gtk::ScrolledWindow {
+                                            set_height_request: 200,
+                                            set_hscrollbar_policy: PolicyType::Never,
+                                            // fetch the next page once the end is in sight
+                                            connect_edge_reached[sender] => move |_, position| {
+                                                if position == gtk::PositionType::Bottom {
+                                                    sender.input(Msg::LoadMoreResults);
+                                                }
+                                            },
+                                            #[local_ref]
+                                            search_results -> gtk::ListView {
+                                            }
+                                        }
+                                    }
+                                }
+                            },
+                            // Main menu
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::OPEN_MENU,
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: main_menu = &gtk::Popover {
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::Button {
+                                            set_label: "Import Playlist…",
+                                            connect_clicked => Msg::ImportPlaylist,
+                                        },
+                                        gtk::Button {
+                                            set_label: "Export Playlist…",
+                                            connect_clicked => Msg::ExportPlaylist,
+                                        },
+                                        gtk::Button {
+                                            set_label: "Merge Duplicate Stations",
+                                            connect_clicked => Msg::MergeDuplicates,
+                                        },
+                                        gtk::Button {
+                                            set_label: "Undo",
+                                            #[watch]
+                                            set_sensitive: !model.undo_stack.is_empty(),
+                                            connect_clicked => Msg::Undo,
+                                        },
+                                        gtk::Separator {},
+                                        gtk::CheckButton {
+                                            set_label: Some("Search an offline copy of radio-browser"),
+                                            set_active: model.settings.offline_search,
+                                            connect_toggled[sender] => move |check| {
+                                                sender.input(Msg::OfflineSearchChanged(check.is_active()));
+                                            },
+                                        },
+                                        gtk::Label {
+                                            set_halign: gtk::Align::Start,
+                                            add_css_class: "dim-label",
+                                            #[watch]
+                                            set_label: &model.mirror_status(),
+                                        },
+                                        gtk::Button {
+                                            set_label: "Update Station Database",
+                                            #[watch]
+                                            set_sensitive: !model.mirror_busy,
+                                            connect_clicked => Msg::DownloadMirror,
+                                        },
+                                        gtk::Button {
+                                            set_label: "Load Station Database…",
+                                            #[watch]
+                                            set_sensitive: !model.mirror_busy,
+                                            connect_clicked => Msg::LoadMirror,
+                                        },
+                                        gtk::Separator {},
+                                        gtk::Label {
+                                            set_halign: gtk::Align::Start,
+                                            set_label: "radio-browser servers",
+                                        },
+                                        gtk::Entry {
+                                            set_placeholder_text: Some("Automatic"),
+                                            set_tooltip_text: Some("Base URLs separated by commas, tried in order"),
+                                            set_text: &model.settings.api_servers.join(", "),
+                                            connect_changed[sender] => move |entry| {
+                                                sender.input(Msg::ApiServersChanged(entry.text().into()));
+                                            },
+                                        },
+                                        gtk::Separator {},
+                                        gtk::CheckButton {
+                                            set_label: Some("Split recordings when the title changes"),
+                                            set_active: model.settings.split_recordings,
+                                            connect_toggled[sender] => move |check| {
+                                                sender.input(Msg::SplitRecordingsChanged(check.is_active()));
+                                            },
+                                        },
+                                    },
+                                },
+                            },
+                            // Add button
+                            gtk::MenuButton {
+                                set_icon_name: icon_names::PLUS,
+                                set_direction: gtk::ArrowType::Down,
+                                #[wrap(Some)]
+                                set_popover: popover = &gtk::Popover {
+                                    set_position: gtk::PositionType::Right,
+
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 5,
+                                        gtk::Label {
+                                            set_halign: gtk::Align::Start,
+                                            set_label: "Name:",
+                                        },
+                                        gtk::Entry {
+                                            connect_changed[sender] => move |entry| {
+                                                let buffer = entry.buffer();
+                                                sender.input(Msg::StationNameChanged(buffer.text().into()));
+                                            }
+                                        },
+                                        gtk::Label {
+                                            set_halign: gtk::Align::Start,
+                                            set_label: "URL:",
+                                        },
+                                        gtk::Entry {
+                                            connect_changed[sender] => move |entry| {
+                                                let buffer = entry.buffer();
+                                                sender.input(Msg::StationUrlChanged(buffer.text().into()));
+                                            }
+                                        },
+                                        gtk::Separator {},
+                                        gtk::Button {
+                                            set_label: "Add Station",
+                                            connect_clicked => Msg::AddStation,
+                                        },
+
+                                    },
+                                },
+                            },
+                        },
+                    },
+
+                    #[local_ref]
+                    // right click menu
+                    ctx_menu -> gtk::Popover {
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: 5,
+                            gtk::Button {
+                                set_label: "Details",
+                                connect_clicked => Msg::ShowDetails,
+                            },
+                            gtk::Button {
+                                set_label: "Edit Station",
+                                connect_clicked => Msg::EditStation,
+                            },
+                            gtk::Button {
+                                set_label: "Move Up",
+                                connect_clicked => Msg::MoveStationUp,
+                            },
+                            gtk::Button {
+                                set_label: "Move Down",
+                                connect_clicked => Msg::MoveStationDown,
+                            },
+                            gtk::Button {
+                                set_label: "Delete Station",
+                                connect_clicked => Msg::DeleteStation,
+                            },
+                            gtk::Separator {},
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
+                                set_spacing: 5,
+                                gtk::Entry {
+                                    set_buffer: &model.group_buffer,
+                                    set_placeholder_text: Some("No group"),
+                                    set_width_chars: 12,
+                                    connect_activate => Msg::SetGroup,
+                                },
+                                gtk::Button {
+                                    set_label: "Set Group",
+                                    connect_clicked => Msg::SetGroup,
+                                },
+                            },
+                        },
+                    },
+
+                    #[local_ref]
+                    edit_popover -> gtk::Popover {
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: 5,
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                set_label: "Name:",
+                            },
+                            gtk::Entry {
+                                set_buffer: &model.edit_name_buffer,
+                            },
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                set_label: "URL:",
+                            },
+                            gtk::Entry {
+                                set_buffer: &model.edit_url_buffer,
+                            },
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                set_label: "Notes:",
+                            },
+                            gtk::TextView {
+                                set_buffer: Some(&model.edit_notes_buffer),
+                                set_wrap_mode: gtk::WrapMode::WordChar,
+                                set_height_request: 60,
+                            },
+                            gtk::Separator {},
+                            gtk::Button {
+                                set_label: "Save Station",
+                                connect_clicked => Msg::SaveStation,
+                            },
+                        },
+                    },
+
+                    #[local_ref]
+                    details_popover -> gtk::Popover {
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: 5,
+                            set_width_request: 250,
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                set_wrap: true,
+                                add_css_class: "heading",
+                                #[watch]
+                                set_label: model
+                                    .details
+                                    .as_ref()
+                                    .map(|station| station.name.as_str())
+                                    .unwrap_or_default(),
+                            },
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                set_wrap: true,
+                                set_selectable: true,
+                                #[watch]
+                                set_label: &model
+                                    .details
+                                    .as_ref()
+                                    .map(Station::details)
+                                    .unwrap_or_default(),
+                            },
+                            gtk::LinkButton::with_label("", "Homepage") {
+                                set_halign: gtk::Align::Start,
+                                #[watch]
+                                set_visible: model
+                                    .details
+                                    .as_ref()
+                                    .is_some_and(|station| station.homepage.is_some()),
+                                #[watch]
+                                set_uri: model
+                                    .details
+                                    .as_ref()
+                                    .and_then(|station| station.homepage.as_deref())
+                                    .unwrap_or_default(),
+                            },
+                        },
+                    },
+
+                    gtk::Paned {
+                        set_vexpand: true,
+                        set_position: 150,
+                        set_shrink_start_child: false,
+
+                        // group sidebar, hidden until there are groups
+                        #[wrap(Some)]
+                        set_start_child = &gtk::ScrolledWindow {
+                            set_hscrollbar_policy: PolicyType::Never,
+                            #[watch]
+                            set_visible: model.station_list.groups.n_items() > 1,
+
+                            #[name = "group_sidebar"]
+                            gtk::ListBox {
+                                add_css_class: "navigation-sidebar",
+                                bind_model: (Some(&model.station_list.groups), |item| {
+                                    let group = item
+                                        .downcast_ref::<gtk::StringObject>()
+                                        .map(|group| group.string().to_string())
+                                        .unwrap_or_default();
+                                    gtk::Label::builder()
+                                        .label(group)
+                                        .halign(gtk::Align::Start)
+                                        .ellipsize(pango::EllipsizeMode::End)
+                                        .build()
+                                        .upcast()
+                                }),
+                                // a group that goes away takes its row, and
+                                // the selection, with it
+                                connect_row_selected[sender] => move |_, row| {
+                                    let position = row.map(|row| row.index() as u32).unwrap_or(0);
+                                    sender.input(Msg::GroupSelected(position));
+                                },
+                            },
+                        },
+
+                        #[wrap(Some)]
+                        set_end_child = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: 5,
+                            gtk::SearchEntry {
+                                set_placeholder_text: Some("Filter by name, tag or group"),
+                                connect_search_changed[sender] => move |entry| {
+                                    sender.input(Msg::StationFilterChanged(entry.text().into()));
+                                },
+                            },
+
+                            // station list
+                            gtk::ScrolledWindow {
+                                set_vexpand: true,
+                                #[local_ref]
+                                station_list_view -> gtk::ListView {
+                                    add_controller = gtk::GestureClick {
+                                        set_button: 0,
+                                        connect_pressed[sender] => move |controller, _, x, y| {
+                                            if controller.current_button() == gtk::gdk::BUTTON_SECONDARY {
+                                                sender.input(Msg::ShowMenu(x, y));
+                                            }
+                                        }
+                                    },
+                                }
+                            },
                         },
                     }
                 }
-            }
+            },
         }
     }
 
@@ -468,37 +1833,145 @@ impl AsyncComponent for Radio {
         root: Self::Root,
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
+        if let Err(error) = saver::migrate_old_locations() {
+            sender.input(Msg::ShowError(error));
+        }
+
         // Initialize the StationList
         let mut station_list = StationList::new(sender.clone());
         station_list.load();
 
+        let settings = saver::load_settings();
+        let mut history = history::History::new(settings.history_limit);
+        history.load();
+
+        let tap = recorder::Tap::default();
         let ctx_menu_handle = gtk::Popover::new();
+        let toast_overlay_handle = adw::ToastOverlay::new();
         let search_results_handle = TypedListView::new();
 
-        let model = Self {
+        let mut model = Self {
             station_list,
             ctx_menu_handle,
+            toast_overlay_handle,
             search_results_handle,
             title: "RelmyVibes".to_string(),
             new_station_name: String::new(),
             new_station_url: String::new(),
             hover_id: None,
             menu_id: 0,
+            menu_rect: Rectangle::new(0, 0, 0, 0),
+            edit_popover_handle: gtk::Popover::new(),
+            edit_name_buffer: gtk::EntryBuffer::default(),
+            edit_url_buffer: gtk::EntryBuffer::default(),
+            edit_notes_buffer: gtk::TextBuffer::new(None),
+            group_buffer: gtk::EntryBuffer::default(),
+            details_popover_handle: gtk::Popover::new(),
+            details: None,
             playing_id: None,
-            player: streamer::load(sender.clone()).unwrap(),
+            player: streamer::load(sender.clone(), &tap).unwrap(),
             volume: 1.0,
-            radio_browser_api: RadioBrowserAPI::new().await.unwrap(),
+            radio_browser_api: None,
+            search_offline: false,
             query: String::new(),
+            search_generation: 0,
+            search_task: None,
+            search_loading: false,
+            search_exhausted: true,
+            search_status: None,
+            search_debounce: 0,
+            settings_debounce: 0,
+            settings_dirty: false,
+            search_cache: saver::load_search_cache(),
+            mirror: None,
+            mirror_busy: true,
+            mirror_checked: 0,
             volume_icon: icon_names::SPEAKER_3.to_string(),
             muted_volume: 1.0,
             muted: false,
+            mpris: mpris::Mpris::new(sender.input_sender().clone()).await.ok(),
+            now_playing: None,
+            history,
+            settings,
+            recorder: None,
+            tap,
+            schedules: saver::load_schedules(),
+            schedule_list: TypedListView::new(),
+            active_schedule: None,
+            scheduled_id: None,
+            new_schedule_station: 0,
+            new_schedule_action: scheduler::Action::Play,
+            new_schedule_weekdays: [false; 7],
+            new_schedule_start: String::new(),
+            new_schedule_end: String::new(),
+            sleep_timer: sleep::SleepTimer::default(),
+            sleep_source: None,
+            alarms: saver::load_alarms(),
+            alarm_list: TypedListView::new(),
+            new_alarm_station: 0,
+            new_alarm_weekdays: [false; 7],
+            new_alarm_time: String::new(),
+            alarm_ringing: false,
+            alarm_station: None,
+            alarm_volume: 1.0,
+            alarm_restore: None,
+            alarm_fade_source: None,
+            alarm_tone: None,
+            last_alarm_minute: None,
+            snoozed: None,
+            file_chooser: None,
+            undo_stack: undo::UndoStack::default(),
+            undo_toast: None,
         };
 
         let station_list_view = &model.station_list.list_view_wrapper.view;
         let ctx_menu = &model.ctx_menu_handle;
+        let edit_popover = &model.edit_popover_handle;
+        let details_popover = &model.details_popover_handle;
+        let toast_overlay = &model.toast_overlay_handle;
         let search_results = &model.search_results_handle.view;
+        let history_list = &model.history.list_view_wrapper.view;
+        let schedule_list_view = &model.schedule_list.view;
+        let alarm_list_view = &model.alarm_list.view;
 
         let widgets = view_output!();
+        widgets
+            .group_sidebar
+            .select_row(widgets.group_sidebar.row_at_index(0).as_ref());
+
+        model.refresh_schedules(&sender);
+        model.refresh_alarms(&sender);
+        let tick_sender = sender.clone();
+        glib::timeout_add_seconds_local(20, move || {
+            tick_sender.input(Msg::SchedulerTick);
+            glib::ControlFlow::Continue
+        });
+        sender.input(Msg::SchedulerTick);
+
+        // reading the station database takes a moment, don't hold up the window
+        sender.oneshot_command(async { CmdOut::MirrorLoaded(mirror::Mirror::load_cached()) });
+
+        let shortcuts = gtk::ShortcutController::new();
+        shortcuts.add_shortcut(shortcut("<Alt>Up", &sender, || Msg::MoveStationUp));
+        shortcuts.add_shortcut(shortcut("<Alt>Down", &sender, || Msg::MoveStationDown));
+        shortcuts.add_shortcut(shortcut("<Control>z", &sender, || Msg::Undo));
+        root.add_controller(shortcuts);
+
+        // the window only hides when closed while schedules are around, so
+        // bring it back when the app is launched again
+        let window = root.clone();
+        let app = relm4::main_application();
+        app.connect_activate(move |_| window.present());
+
+        // buttons on the alarm notification
+        let snooze_action = gio::SimpleAction::new("snooze-alarm", None);
+        let snooze_sender = sender.clone();
+        snooze_action.connect_activate(move |_, _| snooze_sender.input(Msg::SnoozeAlarm));
+        app.add_action(&snooze_action);
+        let stop_action = gio::SimpleAction::new("stop-alarm", None);
+        let stop_sender = sender.clone();
+        stop_action.connect_activate(move |_, _| stop_sender.input(Msg::StopAlarm));
+        app.add_action(&stop_action);
 
         AsyncComponentParts { model, widgets }
     }
@@ -514,27 +1987,29 @@ impl AsyncComponent for Radio {
                 if let Some(station_item) = self.station_list.get_by_id(self.playing_id) {
                     station_item.borrow_mut().inactive();
                 }
-                if let Some(station_item) = self.station_list.get_by_id(self.hover_id) {
+                if let Some(station_item) = self.station_list.get_by_id(Some(id)) {
                     station_item.borrow_mut().active();
                 }
                 self.playing_id = Some(id);
+                self.now_playing = None;
+                self.recorder = None;
                 self.title = station.name.clone();
                 self.player.set_uri(Some(&station.url));
                 self.player.play();
+                if let Some(mpris) = &self.mpris {
+                    let _ = mpris.playing(station, id).await;
+                }
             }
-            Msg::Stop => {
-                if let Some(station_item) = self.station_list.get_by_id(self.playing_id) {
-                    station_item.borrow_mut().inactive();
+            Msg::PlayId(id) => {
+                if let Some(station_item) = self.station_list.get_by_id(Some(id)) {
+                    sender.input(Msg::Play(station_item.borrow().station.clone(), id));
                 }
-                self.playing_id = None;
-                self.title = "RelmyVibes".to_string();
-                self.player.stop();
             }
-            // We do these conversions so the volume behaviour matches what the
-            // user expects (pavucontrol)
-            Msg::ChangeVolume(val) => self
-                .player
-                .set_volume(StreamVolume::convert_volume(Cubic, Linear, val)),
+            Msg::Stop => {
+                self.stop_playback().await;
+                self.stop_alarm();
+            }
+            Msg::ChangeVolume(val) => self.set_volume(val),
             Msg::VolumeChanged(val) => {
                 self.volume = StreamVolume::convert_volume(Linear, Cubic, val);
                 if self.volume == 0.0 {
@@ -546,48 +2021,277 @@ impl AsyncComponent for Radio {
                 } else {
                     self.volume_icon = icon_names::SPEAKER_3.to_string();
                 }
+                if let Some(mpris) = &self.mpris {
+                    let _ = mpris.volume_changed(self.volume).await;
+                }
             }
             Msg::StationNameChanged(name) => self.new_station_name = name,
             Msg::StationUrlChanged(url) => self.new_station_url = url,
             Msg::AddStation => {
                 if !self.new_station_name.is_empty() && !self.new_station_url.is_empty() {
-                    let new_station = Station {
-                        name: self.new_station_name.clone(),
-                        url: self.new_station_url.clone(),
-                    };
-                    self.station_list.append(new_station);
+                    self.add_station(
+                        Station {
+                            name: self.new_station_name.clone(),
+                            url: self.new_station_url.clone(),
+                            ..Default::default()
+                        },
+                        &sender,
+                    );
                 }
             }
+            Msg::AddSearchResult(station) => self.add_station(station, &sender),
             Msg::ShowMenu(x, y) => {
                 if let Some(hover_id) = self.hover_id {
-                    let rect = Rectangle::new(x as i32, (y as i32) + 45, 0, 0);
+                    // the click is in list coordinates but the menu hangs off
+                    // the main box, and what's above and beside the list moves
+                    let (x, y) = self
+                        .ctx_menu_handle
+                        .parent()
+                        .and_then(|parent| {
+                            self.station_list
+                                .list_view_wrapper
+                                .view
+                                .translate_coordinates(&parent, x, y)
+                        })
+                        .unwrap_or((x, y));
+                    let rect = Rectangle::new(x as i32, y as i32, 0, 0);
                     self.ctx_menu_handle.set_pointing_to(Some(&rect));
                     self.ctx_menu_handle.popup();
                     self.menu_id = hover_id;
+                    self.menu_rect = rect;
+                    if let Some(station_item) = self.station_list.get_by_id(Some(hover_id)) {
+                        self.group_buffer.set_text(
+                            station_item
+                                .borrow()
+                                .station
+                                .group
+                                .as_deref()
+                                .unwrap_or_default(),
+                        );
+                    }
+                }
+            }
+            Msg::EditStation => {
+                self.ctx_menu_handle.popdown();
+                if let Some(station_item) = self.station_list.get_by_id(Some(self.menu_id)) {
+                    let station = &station_item.borrow().station;
+                    self.edit_name_buffer.set_text(&station.name);
+                    self.edit_url_buffer.set_text(&station.url);
+                    self.edit_notes_buffer
+                        .set_text(station.notes.as_deref().unwrap_or_default());
+                    self.edit_popover_handle
+                        .set_pointing_to(Some(&self.menu_rect));
+                    self.edit_popover_handle.popup();
+                }
+            }
+            Msg::SaveStation => {
+                let name = self.edit_name_buffer.text().to_string();
+                let url = self.edit_url_buffer.text().to_string();
+                let (start, end) = self.edit_notes_buffer.bounds();
+                let notes = self.edit_notes_buffer.text(&start, &end, false).to_string();
+                if name.is_empty() || url.is_empty() {
+                    self.show_toast("A station needs a name and a URL");
+                } else if let Some(station_item) = self.station_list.get_by_id(Some(self.menu_id)) {
+                    self.edit_popover_handle.popdown();
+                    let before = station_item.borrow().station.clone();
+                    let mut station = before.clone();
+                    let url_changed = url != station.url;
+                    if url_changed {
+                        // the url was typed in by hand now, nothing left to resolve
+                        station.original_url = None;
+                    }
+                    station.name = name;
+                    station.url = url;
+                    station.notes = (!notes.trim().is_empty()).then_some(notes);
+                    self.station_list
+                        .update_by_id(self.menu_id, station.clone());
+                    self.replace_saved_station(&before, &station, &sender);
+                    self.record(vec![undo::Change::Edited {
+                        id: self.menu_id,
+                        before,
+                    }]);
+                    // carry on playing the edited station
+                    if self.playing_id == Some(self.menu_id) {
+                        self.title = station.name.clone();
+                        if url_changed {
+                            sender.input(Msg::Play(station, self.menu_id));
+                        }
+                    }
+                }
+            }
+            Msg::ShowDetails => {
+                self.ctx_menu_handle.popdown();
+                if let Some(station_item) = self.station_list.get_by_id(Some(self.menu_id)) {
+                    self.details = Some(station_item.borrow().station.clone());
+                    self.details_popover_handle
+                        .set_pointing_to(Some(&self.menu_rect));
+                    self.details_popover_handle.popup();
                 }
             }
             Msg::DeleteStation => {
                 self.ctx_menu_handle.popdown();
-                self.station_list.remove_by_id(self.menu_id);
+                if let Some(position) = self.station_list.position_of(self.menu_id)
+                    && let Some(station_item) = self.station_list.get_by_id(Some(self.menu_id))
+                {
+                    let station = station_item.borrow().station.clone();
+                    self.station_list.remove_by_id(self.menu_id);
+                    self.record(vec![undo::Change::Removed {
+                        id: self.menu_id,
+                        station: station.clone(),
+                        position,
+                    }]);
+
+                    let toast = adw::Toast::new(&glib::markup_escape_text(&format!(
+                        "Deleted {}",
+                        station.name
+                    )));
+                    toast.set_button_label(Some("Undo"));
+                    toast.set_timeout(10);
+                    let sender = sender.clone();
+                    toast.connect_button_clicked(move |_| sender.input(Msg::Undo));
+                    self.toast_overlay_handle.add_toast(toast.clone());
+                    self.undo_toast = Some(toast);
+                }
                 self.ctx_menu_handle.popdown();
+                self.refresh_search_results();
+            }
+            Msg::Undo => self.undo(&sender),
+            Msg::MergeDuplicates => {
+                let (merged, changes) = self.station_list.merge_duplicates();
+                self.record(changes);
+                for (removed, station, kept) in &merged {
+                    if let Some(station_item) = self.station_list.get_by_id(Some(*kept)) {
+                        let kept_station = station_item.borrow().station.clone();
+                        self.replace_saved_station(station, &kept_station, &sender);
+                    }
+                    if self.scheduled_id == Some(*removed) {
+                        self.scheduled_id = Some(*kept);
+                    }
+                    if self.playing_id == Some(*removed) {
+                        self.playing_id = Some(*kept);
+                        if let Some(station_item) = self.station_list.get_by_id(Some(*kept)) {
+                            station_item.borrow_mut().active();
+                        }
+                    }
+                }
+                self.show_toast(&match merged.len() {
+                    0 => "No duplicate stations found".to_string(),
+                    1 => "Merged 1 duplicate station".to_string(),
+                    count => format!("Merged {count} duplicate stations"),
+                });
+            }
+            Msg::MoveStationUp => self.move_station(-1),
+            Msg::MoveStationDown => self.move_station(1),
+            Msg::DropStation(id, onto) => {
+                if let Some(position) = self.station_list.position_of(onto)
+                    && let Some(from) = self.station_list.move_to(id, position)
+                {
+                    self.record(vec![undo::Change::Moved { id, from }]);
+                }
+            }
+            Msg::SetGroup => {
+                self.ctx_menu_handle.popdown();
+                let group = self.group_buffer.text().trim().to_string();
+                if let Some(station_item) = self.station_list.get_by_id(Some(self.menu_id)) {
+                    let before = station_item.borrow().station.clone();
+                    let mut station = before.clone();
+                    // an empty group takes the station out of whatever group it was in
+                    station.group = (!group.is_empty()).then_some(group);
+                    self.station_list.update_by_id(self.menu_id, station);
+                    self.record(vec![undo::Change::Edited {
+                        id: self.menu_id,
+                        before,
+                    }]);
+                }
+            }
+            Msg::GroupSelected(position) => {
+                // whatever was under the pointer may not be there any more
+                self.hover_id = None;
+                self.station_list.set_group_filter(position);
+            }
+            Msg::StationFilterChanged(query) => {
+                self.hover_id = None;
+                self.station_list.set_query(&query);
             }
             Msg::SetHoverId(id) => {
                 self.hover_id = id;
             }
-            Msg::SearchQueryChanged(query) => self.query = query,
+            Msg::SearchQueryChanged(query) => {
+                self.query = query;
+                self.debounce_search(&sender);
+            }
+            Msg::SearchFilterChanged(filter) => {
+                self.settings.search_filters.set(filter);
+                self.debounce_save_settings(&sender);
+                self.debounce_search(&sender);
+            }
             Msg::Search => {
-                self.search_results_handle.clear();
-                root.set_cursor_from_name(Some("wait"));
-                let _: Vec<_> = search::search(self.radio_browser_api.clone(), self.query.clone())
-                    .await
-                    .unwrap()
-                    .iter()
-                    .map(|item| {
-                        self.search_results_handle
-                            .append(SearchItem::new(item.clone(), sender.clone()))
-                    })
-                    .collect();
-                root.set_cursor_from_name(Some("default"));
+                // searching right away, whatever was waiting can go
+                self.search_debounce += 1;
+                self.start_search(&sender);
+            }
+            Msg::DebouncedSearch(debounce) => {
+                if debounce == self.search_debounce {
+                    self.start_search(&sender);
+                }
+            }
+            Msg::DebouncedSaveSettings(debounce) => {
+                if debounce == self.settings_debounce && self.settings_dirty {
+                    self.settings_dirty = false;
+                    if let Err(error) = saver::save_settings(&self.settings) {
+                        self.show_error(&error);
+                    }
+                }
+            }
+            Msg::OfflineSearchChanged(offline) => {
+                self.settings.offline_search = offline;
+                if let Err(error) = saver::save_settings(&self.settings) {
+                    self.show_error(&error);
+                }
+                self.refresh_mirror(&sender, false);
+            }
+            Msg::DownloadMirror => self.refresh_mirror(&sender, true),
+            Msg::LoadMirror => {
+                let dialog = gtk::FileChooserNative::new(
+                    Some("Load Station Database"),
+                    Some(root),
+                    gtk::FileChooserAction::Open,
+                    Some("Load"),
+                    Some("Cancel"),
+                );
+                let filter = gtk::FileFilter::new();
+                filter.set_name(Some("radio-browser station dumps"));
+                filter.add_pattern("*.json");
+                dialog.add_filter(&filter);
+                // a one off dump shouldn't have to replace the downloaded copy
+                dialog.add_choice("keep", "Use instead of the downloaded copy", &[]);
+                dialog.set_choice("keep", "false");
+                let sender = sender.clone();
+                dialog.connect_response(move |dialog, response| {
+                    if response == gtk::ResponseType::Accept
+                        && let Some(path) = dialog.file().and_then(|file| file.path())
+                    {
+                        let keep = dialog.choice("keep").is_some_and(|keep| keep == "true");
+                        sender.input(Msg::LoadMirrorFile(path, keep));
+                    }
+                });
+                dialog.show();
+                self.file_chooser = Some(dialog);
+            }
+            Msg::ApiServersChanged(text) => {
+                self.settings.api_servers = search::parse_servers(&text);
+                self.debounce_save_settings(&sender);
+            }
+            Msg::LoadMirrorFile(path, keep) => {
+                self.mirror_busy = true;
+                sender.oneshot_command(async move {
+                    CmdOut::MirrorLoaded(mirror::Mirror::load_file(&path, keep).map(Some))
+                });
+            }
+            Msg::LoadMoreResults => {
+                if !self.search_loading && !self.search_exhausted {
+                    self.search_page(&sender);
+                }
             }
             Msg::ToggleMute => {
                 if self.muted {
@@ -600,8 +2304,914 @@ impl AsyncComponent for Radio {
                 }
                 sender.input(Msg::ChangeVolume(self.volume))
             }
+            Msg::StreamError(error) => {
+                // a ringing alarm keeps its notification and volume to
+                // restore, only the station makes way for the fallback
+                if self.alarm_ringing {
+                    self.stop_playback().await;
+                    sender.input(Msg::AlarmFallback);
+                } else {
+                    sender.input(Msg::Stop);
+                }
+                self.show_error(&error);
+            }
+            Msg::ShowError(error) => self.show_error(&error),
+            Msg::NowPlaying(now_playing) => {
+                // media info gets updated a lot more often than the title changes
+                if self.playing_id.is_some() && now_playing != self.now_playing {
+                    self.now_playing = now_playing;
+                    if self.settings.split_recordings
+                        && let Some(recorder) = &self.recorder
+                        && let Some(now_playing) = &self.now_playing
+                    {
+                        recorder.split(now_playing.to_string());
+                    }
+                    if let Some(mpris) = &self.mpris {
+                        let _ = mpris.now_playing(self.now_playing.clone()).await;
+                    }
+                    if let Some(now_playing) = &self.now_playing
+                        && let Some(station_item) = self.station_list.get_by_id(self.playing_id)
+                    {
+                        self.history
+                            .record(&station_item.borrow().station, now_playing);
+                    }
+                }
+            }
+            Msg::HistoryQueryChanged(query) => self.history.set_query(query),
+            Msg::HistoryStationChanged(position) => self.history.set_station(position),
+            Msg::HistoryLimitChanged(limit) => {
+                self.settings.history_limit = limit;
+                self.history.set_limit(limit);
+                if let Err(error) = saver::save_settings(&self.settings) {
+                    self.show_error(&error);
+                }
+            }
+            Msg::ToggleRecording => {
+                if self.recorder.is_some() {
+                    self.recorder = None;
+                } else if let Some(station_item) = self.station_list.get_by_id(self.playing_id) {
+                    self.recorder = Some(recorder::Recorder::start(
+                        &station_item.borrow().station,
+                        saver::get_recordings_dir(),
+                        &self.tap,
+                        sender.clone(),
+                    ));
+                }
+            }
+            Msg::RecordingError(error) => {
+                self.recorder = None;
+                self.show_error(&error);
+            }
+            Msg::SplitRecordingsChanged(split) => {
+                self.settings.split_recordings = split;
+                if let Err(error) = saver::save_settings(&self.settings) {
+                    self.show_error(&error);
+                }
+            }
+            Msg::SchedulerTick => {
+                self.run_schedules(&sender);
+                self.run_alarms(&sender);
+                self.refresh_mirror(&sender, false);
+                // history is written in batches rather than on every track
+                if let Err(error) = self.history.flush() {
+                    self.show_error(&error);
+                }
+            }
+            Msg::ScheduleStationChanged(position) => self.new_schedule_station = position,
+            Msg::ScheduleActionChanged(position) => {
+                self.new_schedule_action = match position {
+                    0 => scheduler::Action::Play,
+                    _ => scheduler::Action::Record,
+                }
+            }
+            Msg::ScheduleWeekdayToggled(day, enabled) => self.new_schedule_weekdays[day] = enabled,
+            Msg::ScheduleStartChanged(start) => self.new_schedule_start = start,
+            Msg::ScheduleEndChanged(end) => self.new_schedule_end = end,
+            Msg::AddSchedule => {
+                let station = self
+                    .station_list
+                    .list_view_wrapper
+                    .get(self.new_schedule_station)
+                    .map(|item| item.borrow().station.clone());
+                match (
+                    station,
+                    scheduler::parse_time(&self.new_schedule_start),
+                    scheduler::parse_time(&self.new_schedule_end),
+                ) {
+                    (_, Some(start), Some(end)) if start == end => {
+                        self.show_toast("A schedule needs different start and end times")
+                    }
+                    (Some(station), Some(start), Some(end))
+                        if self.new_schedule_weekdays.contains(&true) =>
+                    {
+                        self.schedules.push(scheduler::Schedule {
+                            station,
+                            action: self.new_schedule_action,
+                            weekdays: self.new_schedule_weekdays,
+                            start,
+                            end,
+                        });
+                        self.save_schedules(&sender);
+                    }
+                    _ => self.show_toast("Pick a station, at least one day and times like 08:00"),
+                }
+            }
+            Msg::StartSleepTimer(minutes) => {
+                if let Some(source) = self.sleep_source.take() {
+                    source.remove();
+                }
+                // a restart during the fade goes back to full volume
+                if let Some(volume) = self.sleep_timer.start(minutes) {
+                    self.set_volume(volume);
+                }
+                let tick_sender = sender.clone();
+                self.sleep_source = Some(glib::timeout_add_seconds_local(1, move || {
+                    tick_sender.input(Msg::SleepTick);
+                    glib::ControlFlow::Continue
+                }));
+            }
+            Msg::CancelSleepTimer => self.cancel_sleep_timer(),
+            Msg::SleepTick => match self.sleep_timer.tick(self.volume) {
+                sleep::Tick::Wait => {}
+                sleep::Tick::Fade(volume) => self.set_volume(volume),
+                sleep::Tick::Stop => {
+                    // stop right away so restoring the volume can't blip
+                    self.player.stop();
+                    sender.input(Msg::Stop);
+                    self.cancel_sleep_timer();
+                }
+            },
+            Msg::AlarmStationChanged(position) => self.new_alarm_station = position,
+            Msg::AlarmWeekdayToggled(day, enabled) => self.new_alarm_weekdays[day] = enabled,
+            Msg::AlarmTimeChanged(time) => self.new_alarm_time = time,
+            Msg::AddAlarm => {
+                let station = self
+                    .station_list
+                    .list_view_wrapper
+                    .get(self.new_alarm_station)
+                    .map(|item| item.borrow().station.clone());
+                match (station, scheduler::parse_time(&self.new_alarm_time)) {
+                    (Some(station), Some(time)) if self.new_alarm_weekdays.contains(&true) => {
+                        self.alarms.push(alarm::Alarm {
+                            station,
+                            weekdays: self.new_alarm_weekdays,
+                            time,
+                        });
+                        self.save_alarms(&sender);
+                    }
+                    _ => self.show_toast("Pick a station, at least one day and a time like 07:00"),
+                }
+            }
+            Msg::DeleteAlarm(index) => {
+                if index < self.alarms.len() {
+                    self.alarms.remove(index);
+                    self.save_alarms(&sender);
+                }
+            }
+            Msg::AlarmFadeTick => {
+                let volume = (self.volume + self.alarm_volume / alarm::FADE_SECONDS as f64)
+                    .min(self.alarm_volume);
+                self.set_volume(volume);
+                if volume >= self.alarm_volume
+                    && let Some(source) = self.alarm_fade_source.take()
+                {
+                    source.remove();
+                }
+            }
+            // the stream failed (or the station is gone), ring with a tone instead
+            Msg::AlarmFallback => {
+                if let Some(source) = self.alarm_fade_source.take() {
+                    source.remove();
+                }
+                match alarm::Tone::play() {
+                    Ok(tone) => self.alarm_tone = Some(tone),
+                    Err(error) => self.show_error(&error),
+                }
+            }
+            Msg::SnoozeAlarm => {
+                if self.alarm_ringing
+                    && let Some(station) = self.alarm_station.clone()
+                {
+                    self.snoozed = glib::DateTime::now_local()
+                        .ok()
+                        .map(|now| (station, now.to_unix() + alarm::SNOOZE_SECONDS));
+                    sender.input(Msg::Stop);
+                }
+            }
+            Msg::StopAlarm => {
+                if self.alarm_ringing {
+                    sender.input(Msg::Stop);
+                }
+            }
+            Msg::ImportPlaylist => {
+                let dialog = gtk::FileChooserNative::new(
+                    Some("Import Playlist"),
+                    Some(root),
+                    gtk::FileChooserAction::Open,
+                    Some("Import"),
+                    Some("Cancel"),
+                );
+                dialog.add_filter(&playlist_filter());
+                let sender = sender.clone();
+                dialog.connect_response(move |dialog, response| {
+                    if response == gtk::ResponseType::Accept
+                        && let Some(path) = dialog.file().and_then(|file| file.path())
+                    {
+                        sender.input(Msg::ImportPlaylistFile(path));
+                    }
+                });
+                dialog.show();
+                self.file_chooser = Some(dialog);
+            }
+            Msg::ExportPlaylist => {
+                let dialog = gtk::FileChooserNative::new(
+                    Some("Export Playlist"),
+                    Some(root),
+                    gtk::FileChooserAction::Save,
+                    Some("Export"),
+                    Some("Cancel"),
+                );
+                dialog.add_filter(&playlist_filter());
+                dialog.set_current_name("stations.m3u");
+                let sender = sender.clone();
+                dialog.connect_response(move |dialog, response| {
+                    if response == gtk::ResponseType::Accept
+                        && let Some(path) = dialog.file().and_then(|file| file.path())
+                    {
+                        sender.input(Msg::ExportPlaylistFile(path));
+                    }
+                });
+                dialog.show();
+                self.file_chooser = Some(dialog);
+            }
+            Msg::ImportPlaylistFile(path) => match playlist::import(&path) {
+                Ok(stations) => {
+                    let count = stations.len();
+                    let stations: Vec<Station> = stations
+                        .into_iter()
+                        .filter(|station| self.station_list.find_duplicate(station).is_none())
+                        .collect();
+                    let skipped = count - stations.len();
+                    self.show_toast(&format!("Importing {} stations…", stations.len()));
+                    sender.oneshot_command(async move {
+                        let urls = stations.iter().map(|station| station.url.clone()).collect();
+                        let resolved = resolver::resolve_all(urls).await;
+                        CmdOut::PlaylistResolved {
+                            stations: stations.into_iter().zip(resolved).collect(),
+                            skipped,
+                        }
+                    });
+                }
+                Err(error) => self.show_error(&error),
+            },
+            Msg::ExportPlaylistFile(path) => {
+                if let Err(error) = playlist::export(&path, &self.station_list.stations()) {
+                    self.show_error(&error);
+                }
+            }
+            Msg::DeleteSchedule(index) => {
+                if index < self.schedules.len() {
+                    if self.active_schedule == Some(index) {
+                        self.end_schedule(&sender);
+                    }
+                    self.schedules.remove(index);
+                    self.active_schedule = match self.active_schedule {
+                        Some(active) if active > index => Some(active - 1),
+                        active => active,
+                    };
+                    self.save_schedules(&sender);
+                }
+            }
+        }
+    }
+
+    async fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match msg {
+            // a newer search has started since, nobody wants these any more
+            CmdOut::SearchResults {
+                generation, api, ..
+            } if generation != self.search_generation => self.keep_api(api),
+            CmdOut::SearchResults {
+                key,
+                api,
+                results: Ok(stations),
+                ..
+            } => {
+                self.keep_api(api);
+                self.search_loading = false;
+                if let Some(key) = key {
+                    self.search_cache.insert(key, stations.clone());
+                    if let Err(error) = saver::save_search_cache(&self.search_cache) {
+                        self.show_error(&error);
+                    }
+                }
+                self.show_search_results(stations, &sender);
+            }
+            CmdOut::SearchResults {
+                key,
+                api,
+                results: Err(error),
+                ..
+            } => {
+                self.keep_api(api);
+                self.search_offline =
+                    self.radio_browser_api.is_none() && self.settings.api_servers.is_empty();
+                self.search_loading = false;
+                // old results are better than none
+                match key.and_then(|key| self.search_cache.get(&key, None)) {
+                    Some(stations) => {
+                        self.show_search_results(stations, &sender);
+                        self.search_status = Some(format!("Showing saved results, {error}"));
+                    }
+                    // scrolling down again will retry the page
+                    None => self.search_status = Some(error.to_string()),
+                }
+            }
+            CmdOut::StationResolved(mut station, resolved) => {
+                // still add it, the url might be fine for gstreamer even if
+                // we couldn't make sense of it
+                if let Err(error) = apply_resolved(&mut station, resolved) {
+                    self.show_error(&error);
+                }
+                // it may have resolved to something that was already saved
+                match self.station_list.find_duplicate(&station) {
+                    Some(_) => {
+                        self.show_toast(&format!("{} is already in your list", station.name))
+                    }
+                    None => {
+                        let id = self.station_list.append(station);
+                        self.record(vec![undo::Change::Added(id)]);
+                        self.refresh_search_results();
+                    }
+                }
+            }
+            CmdOut::PlaylistResolved {
+                stations,
+                mut skipped,
+            } => {
+                let mut added = vec![];
+                let mut unresolved = 0;
+                for (mut station, resolved) in stations {
+                    if apply_resolved(&mut station, resolved).is_err() {
+                        unresolved += 1;
+                    }
+                    // resolving can turn up duplicates, of the list or of
+                    // each other
+                    if self.station_list.find_duplicate(&station).is_some() {
+                        skipped += 1;
+                        continue;
+                    }
+                    let position = self.station_list.list_view_wrapper.len();
+                    added.push(undo::Change::Added(
+                        self.station_list.insert_unsaved(position, station),
+                    ));
+                }
+                let count = added.len();
+                if count > 0 {
+                    self.station_list.save();
+                }
+                self.record(added);
+                self.refresh_search_results();
+                let mut message = format!("Imported {count} stations");
+                if skipped > 0 {
+                    message.push_str(&format!(", {skipped} were already in your list"));
+                }
+                if unresolved > 0 {
+                    message.push_str(&format!(", {unresolved} could not be checked"));
+                }
+                self.show_toast(&message);
+            }
+            CmdOut::FaviconFetched(url, Some(_)) => self.station_list.refresh_favicon(&url),
+            CmdOut::FaviconFetched(_, None) => {}
+            CmdOut::MirrorLoaded(loaded) => {
+                self.mirror_busy = false;
+                match loaded {
+                    Ok(Some(mirror)) => self.mirror = Some(Arc::new(mirror)),
+                    Ok(None) => {}
+                    Err(error) => self.show_error(&error),
+                }
+                // the first time round this is the copy from last run, which
+                // may want updating
+                self.refresh_mirror(&sender, false);
+            }
+        }
+    }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        // nowhere left to show an error
+        let _ = self.history.flush();
+        if self.settings_dirty {
+            let _ = saver::save_settings(&self.settings);
+        }
+    }
+}
+
+fn shortcut(
+    trigger: &str,
+    sender: &AsyncComponentSender<Radio>,
+    msg: fn() -> Msg,
+) -> gtk::Shortcut {
+    let sender = sender.clone();
+    gtk::Shortcut::new(
+        gtk::ShortcutTrigger::parse_string(trigger),
+        Some(gtk::CallbackAction::new(move |_, _| {
+            sender.input(msg());
+            Propagation::Stop
+        })),
+    )
+}
+
+fn playlist_filter() -> gtk::FileFilter {
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Playlists"));
+    for pattern in ["*.m3u", "*.m3u8", "*.pls", "*.xspf"] {
+        filter.add_pattern(pattern);
+    }
+    filter
+}
+
+impl Radio {
+    // We do these conversions so the volume behaviour matches what the
+    // user expects (pavucontrol)
+    fn set_volume(&self, val: f64) {
+        self.player
+            .set_volume(StreamVolume::convert_volume(Cubic, Linear, val));
+    }
+
+    // Stops the countdown and puts the volume back to where it was before
+    // any fading happened
+    fn cancel_sleep_timer(&mut self) {
+        if let Some(source) = self.sleep_source.take() {
+            source.remove();
+        }
+        if let Some(volume) = self.sleep_timer.cancel() {
+            self.set_volume(volume);
+        }
+    }
+
+    // Move up/down apply to the station the right click menu is open for,
+    // otherwise the selected one, otherwise whatever is playing
+    fn move_station(&mut self, offset: i32) {
+        let target = if self.ctx_menu_handle.is_visible() {
+            Some(self.menu_id)
+        } else {
+            self.station_list.selected_id().or(self.playing_id)
+        };
+        if let Some(id) = target
+            && let Some(position) = self.station_list.visible_neighbour(id, offset)
+            && let Some(from) = self.station_list.move_to(id, position)
+        {
+            // keep hold of it so the shortcut can be pressed again
+            self.station_list.select(id);
+            self.record(vec![undo::Change::Moved { id, from }]);
+        }
+    }
+
+    fn record(&mut self, step: Vec<undo::Change>) {
+        if let Some(toast) = self.undo_toast.take() {
+            toast.dismiss();
+        }
+        self.undo_stack.push(step);
+    }
+
+    fn undo(&mut self, sender: &AsyncComponentSender<Self>) {
+        if let Some(toast) = self.undo_toast.take() {
+            toast.dismiss();
+        }
+        let Some(step) = self.undo_stack.pop() else {
+            self.show_toast("Nothing to undo");
+            return;
+        };
+        for change in step.into_iter().rev() {
+            match change {
+                undo::Change::Added(id) => self.station_list.remove_by_id(id),
+                undo::Change::Removed {
+                    id,
+                    station,
+                    position,
+                } => {
+                    // back under its old id, so older steps still find it
+                    self.station_list.insert_with_id(position, station, id);
+                    self.station_list.save();
+                    if self.playing_id == Some(id)
+                        && let Some(station_item) = self.station_list.get_by_id(Some(id))
+                    {
+                        station_item.borrow_mut().active();
+                    }
+                }
+                undo::Change::Edited { id, before } => {
+                    let Some(station_item) = self.station_list.get_by_id(Some(id)) else {
+                        continue;
+                    };
+                    let station = station_item.borrow().station.clone();
+                    // same as saving an edit, the playing station follows
+                    if self.playing_id == Some(id) {
+                        self.title = before.name.clone();
+                        if station.url != before.url {
+                            sender.input(Msg::Play(before.clone(), id));
+                        }
+                    }
+                    self.replace_saved_station(&station, &before, sender);
+                    self.station_list.update_by_id(id, before);
+                }
+                undo::Change::Moved { id, from } => {
+                    self.station_list.move_to(id, from);
+                }
+            }
+        }
+        self.refresh_search_results();
+    }
+
+    // Station urls are often playlists or pages that redirect to the stream,
+    // so work out where it really is before adding it
+    fn add_station(&self, station: Station, sender: &AsyncComponentSender<Self>) {
+        if self.station_list.find_duplicate(&station).is_some() {
+            self.show_toast(&format!("{} is already in your list", station.name));
+            return;
+        }
+        sender.oneshot_command(async move {
+            let resolved = resolver::resolve(&station.url).await;
+            CmdOut::StationResolved(station, resolved)
+        });
+    }
+
+    // Fetch the page of results after the ones already shown, in the
+    // background so the window stays responsive
+    fn search_page(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.search_status = None;
+        let offset = self.search_results_handle.len();
+        let mirror = self.mirror.clone().filter(|_| self.settings.offline_search);
+        // searching the mirror is quicker than the cache
+        let key = match mirror {
+            Some(_) => None,
+            None => Some(search::Cache::key(
+                &self.query,
+                &self.settings.search_filters,
+                offset,
+            )),
+        };
+        if let Some(stations) = key
+            .as_ref()
+            .and_then(|key| self.search_cache.get(key, Some(search::FRESH_SECONDS)))
+        {
+            self.show_search_results(stations, sender);
+            return;
+        }
+
+        self.search_loading = true;
+        let generation = self.search_generation;
+        let mut api = self.radio_browser_api.clone();
+        let servers = self.settings.api_servers.clone();
+        let query = self.query.clone();
+        let filters = self.settings.search_filters.clone();
+        let command_sender = sender.command_sender().clone();
+        self.search_task = Some(relm4::spawn(async move {
+            let results = search::search(&mut api, servers, mirror, query, filters, offset).await;
+            command_sender.emit(CmdOut::SearchResults {
+                generation,
+                key,
+                api,
+                results,
+            });
+        }));
+    }
+
+    fn keep_api(&mut self, api: Option<RadioBrowserAPI>) {
+        if api.is_some() {
+            self.radio_browser_api = api;
+            self.search_offline = false;
+        }
+    }
+
+    fn show_search_results(&mut self, stations: Vec<Station>, sender: &AsyncComponentSender<Self>) {
+        self.search_exhausted = stations.len() < search::PAGE_SIZE as usize;
+        for station in stations {
+            let in_list = self.station_list.find_duplicate(&station).is_some();
+            self.search_results_handle
+                .append(SearchItem::new(station, in_list, sender.clone()));
+        }
+        if self.search_results_handle.len() == 0 {
+            self.search_status = Some("No stations found".to_string());
+        }
+    }
+
+    // Download a new copy of the station database when offline search is on
+    // and the one we have is missing or old, or right away if forced
+    fn refresh_mirror(&mut self, sender: &AsyncComponentSender<Self>, force: bool) {
+        let now = glib::DateTime::now_local()
+            .map(|now| now.to_unix())
+            .unwrap_or_default();
+        let wanted = self.settings.offline_search
+            && self.mirror.as_ref().is_none_or(|mirror| mirror.is_stale())
+            // a failed download gets another go after an hour
+            && now - self.mirror_checked > 60 * 60;
+        if self.mirror_busy || !(force || wanted) {
+            return;
+        }
+        self.mirror_busy = true;
+        self.mirror_checked = now;
+        let servers = self.settings.api_servers.clone();
+        sender.oneshot_command(async move {
+            CmdOut::MirrorLoaded(mirror::Mirror::download(servers).await.map(Some))
+        });
+    }
+
+    fn mirror_status(&self) -> String {
+        match &self.mirror {
+            _ if self.mirror_busy => "Updating the station database…".to_string(),
+            Some(mirror) => {
+                let date = glib::DateTime::from_unix_local(mirror.timestamp)
+                    .and_then(|date| date.format("%x"))
+                    .map(|date| date.to_string())
+                    .unwrap_or_default();
+                format!("{} stations, from {date}", mirror.station_count())
+            }
+            None => "No offline station database yet".to_string(),
+        }
+    }
+
+    // Search once typing has paused for a moment rather than on every key
+    fn debounce_search(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.search_debounce += 1;
+        if self.query.trim().is_empty() && self.settings.search_filters.is_empty() {
+            return;
+        }
+        let debounce = self.search_debounce;
+        let sender = sender.clone();
+        glib::timeout_add_local_once(Duration::from_millis(400), move || {
+            sender.input(Msg::DebouncedSearch(debounce))
+        });
+    }
+
+    // Typing into a filter or the server list changes the settings on every
+    // keystroke, only write them out once it has stopped for a moment
+    fn debounce_save_settings(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.settings_debounce += 1;
+        self.settings_dirty = true;
+        let debounce = self.settings_debounce;
+        let sender = sender.clone();
+        glib::timeout_add_local_once(Duration::from_secs(1), move || {
+            sender.input(Msg::DebouncedSaveSettings(debounce))
+        });
+    }
+
+    fn start_search(&mut self, sender: &AsyncComponentSender<Self>) {
+        if let Some(task) = self.search_task.take() {
+            task.abort();
+            self.search_loading = false;
+        }
+        self.search_generation += 1;
+        self.search_results_handle.clear();
+        self.search_exhausted = false;
+        self.search_page(sender);
+    }
+
+    // keep the "already in your list" marks on search results up to date
+    fn refresh_search_results(&self) {
+        for x in 0..self.search_results_handle.len() {
+            if let Some(item) = self.search_results_handle.get(x) {
+                let in_list = self
+                    .station_list
+                    .find_duplicate(&item.borrow().station)
+                    .is_some();
+                item.borrow_mut().set_in_list(in_list);
+            }
         }
     }
+
+    fn show_error(&self, error: &Error) {
+        self.show_toast(&error.to_string());
+    }
+
+    fn show_toast(&self, message: &str) {
+        let toast = adw::Toast::new(&glib::markup_escape_text(message));
+        toast.set_timeout(10);
+        self.toast_overlay_handle.add_toast(toast);
+    }
+
+    fn refresh_schedules(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.schedule_list.clear();
+        for (index, schedule) in self.schedules.iter().enumerate() {
+            self.schedule_list.append(scheduler::RuleItem::new(
+                schedule.describe(),
+                "Delete schedule",
+                Msg::DeleteSchedule,
+                index,
+                sender.clone(),
+            ));
+        }
+    }
+
+    fn save_schedules(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.refresh_schedules(sender);
+        if let Err(error) = saver::save_schedules(&self.schedules) {
+            self.show_error(&error);
+        }
+    }
+
+    fn refresh_alarms(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.alarm_list.clear();
+        for (index, alarm) in self.alarms.iter().enumerate() {
+            self.alarm_list.append(scheduler::RuleItem::new(
+                alarm.describe(),
+                "Delete alarm",
+                Msg::DeleteAlarm,
+                index,
+                sender.clone(),
+            ));
+        }
+    }
+
+    fn save_alarms(&mut self, sender: &AsyncComponentSender<Self>) {
+        self.refresh_alarms(sender);
+        if let Err(error) = saver::save_alarms(&self.alarms) {
+            self.show_error(&error);
+        }
+    }
+
+    // Schedules and alarms keep a copy of their station, so it has to follow
+    // edits and the station being merged into another
+    fn replace_saved_station(
+        &mut self,
+        old: &Station,
+        station: &Station,
+        sender: &AsyncComponentSender<Self>,
+    ) {
+        let schedules = self
+            .schedules
+            .iter_mut()
+            .map(|schedule| &mut schedule.station);
+        if scheduler::replace_station(schedules, old, station) {
+            self.save_schedules(sender);
+        }
+        let alarms = self.alarms.iter_mut().map(|alarm| &mut alarm.station);
+        if scheduler::replace_station(alarms, old, station) {
+            self.save_alarms(sender);
+        }
+        let snoozed = self.snoozed.iter_mut().map(|(snoozed, _)| snoozed);
+        scheduler::replace_station(snoozed, old, station);
+    }
+
+    fn run_alarms(&mut self, sender: &AsyncComponentSender<Self>) {
+        let Ok(now) = glib::DateTime::now_local() else {
+            return;
+        };
+        let minute_stamp = now.to_unix() / 60;
+        if self.last_alarm_minute == Some(minute_stamp) {
+            return;
+        }
+        let weekday = now.day_of_week() as usize;
+        let minute = (now.hour() * 60 + now.minute()) as u32;
+        let due = self
+            .alarms
+            .iter()
+            .find(|alarm| alarm.is_due(weekday, minute))
+            .map(|alarm| alarm.station.clone())
+            .or_else(|| match &self.snoozed {
+                Some((station, until)) if now.to_unix() >= *until => Some(station.clone()),
+                _ => None,
+            });
+        if let Some(station) = due {
+            self.last_alarm_minute = Some(minute_stamp);
+            self.snoozed = None;
+            self.ring_alarm(station, sender);
+        }
+    }
+
+    // Start the station silently and fade it in
+    fn ring_alarm(&mut self, station: Station, sender: &AsyncComponentSender<Self>) {
+        self.stop_alarm();
+        // an alarm you can't hear isn't much use
+        self.alarm_volume = if self.muted || self.volume == 0.0 {
+            1.0
+        } else {
+            self.volume
+        };
+        self.alarm_restore = Some((self.volume, self.muted));
+        self.muted = false;
+
+        let notification = gio::Notification::new("Alarm");
+        notification.set_body(Some(&station.name));
+        notification.add_button("Snooze", "app.snooze-alarm");
+        notification.add_button("Stop", "app.stop-alarm");
+        relm4::main_application().send_notification(Some("alarm"), &notification);
+
+        self.alarm_ringing = true;
+        self.alarm_station = Some(station.clone());
+        let found = self
+            .station_list
+            .find_station(&station)
+            .and_then(|id| self.station_list.get_by_id(Some(id)));
+        match found {
+            Some(station_item) => {
+                let (station, id) = {
+                    let item = station_item.borrow();
+                    (item.station.clone(), item.id)
+                };
+                self.set_volume(0.0);
+                sender.input(Msg::Play(station, id));
+                let tick_sender = sender.clone();
+                self.alarm_fade_source = Some(glib::timeout_add_seconds_local(1, move || {
+                    tick_sender.input(Msg::AlarmFadeTick);
+                    glib::ControlFlow::Continue
+                }));
+            }
+            None => sender.input(Msg::AlarmFallback),
+        }
+    }
+
+    async fn stop_playback(&mut self) {
+        if let Some(station_item) = self.station_list.get_by_id(self.playing_id) {
+            station_item.borrow_mut().inactive();
+        }
+        self.playing_id = None;
+        self.now_playing = None;
+        self.recorder = None;
+        self.title = "RelmyVibes".to_string();
+        self.player.stop();
+        if let Some(mpris) = &self.mpris {
+            let _ = mpris.stopped().await;
+        }
+    }
+
+    fn stop_alarm(&mut self) {
+        if let Some(source) = self.alarm_fade_source.take() {
+            source.remove();
+        }
+        self.alarm_tone = None;
+        if self.alarm_ringing {
+            self.alarm_ringing = false;
+            if let Some((volume, muted)) = self.alarm_restore.take() {
+                self.muted = muted;
+                self.set_volume(volume);
+            }
+            relm4::main_application().withdraw_notification("alarm");
+        }
+    }
+
+    // Start whichever schedule just became active and stop the one that
+    // just ended. A station stopped by hand stays stopped until the next
+    // schedule comes around.
+    fn run_schedules(&mut self, sender: &AsyncComponentSender<Self>) {
+        let active = self
+            .schedules
+            .iter()
+            .position(|schedule| schedule.is_active_now());
+        if active == self.active_schedule {
+            return;
+        }
+        self.end_schedule(sender);
+        if let Some(index) = active {
+            let schedule = &self.schedules[index];
+            let found = self
+                .station_list
+                .find_station(&schedule.station)
+                .and_then(|id| self.station_list.get_by_id(Some(id)));
+            match found {
+                Some(station_item) => {
+                    let (station, id) = {
+                        let item = station_item.borrow();
+                        (item.station.clone(), item.id)
+                    };
+                    sender.input(Msg::Play(station, id));
+                    self.scheduled_id = Some(id);
+                    if schedule.action == scheduler::Action::Record {
+                        sender.input(Msg::ToggleRecording);
+                    }
+                }
+                None => self.show_toast(&format!(
+                    "Scheduled station {} is no longer saved",
+                    schedule.station.name
+                )),
+            }
+        }
+        self.active_schedule = active;
+    }
+
+    // Stop what the running schedule started, unless someone has since
+    // switched to another station.
+    fn end_schedule(&mut self, sender: &AsyncComponentSender<Self>) {
+        if let Some(id) = self.scheduled_id.take()
+            && self.playing_id == Some(id)
+        {
+            sender.input(Msg::Stop);
+        }
+        self.active_schedule = None;
+    }
+}
+
+// Swap in the url a station resolved to, keeping what it was added with
+fn apply_resolved(station: &mut Station, resolved: Result<String, Error>) -> Result<(), Error> {
+    let url = resolved?;
+    if url != station.url {
+        station.original_url = Some(std::mem::replace(&mut station.url, url));
+    }
+    Ok(())
 }
 
 fn main() {
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, Station, saver,
+    search::{self, Filters, Order},
+};
+
+// radio-browser asks for the full list to be fetched no more than about once
+// a day, a week old copy is plenty fresh for finding stations
+pub const REFRESH_SECONDS: i64 = 7 * 24 * 60 * 60;
+// round robin over every radio-browser server, for when none were picked
+const DUMP_URL: &str = "https://all.api.radio-browser.info/json/stations?hidebroken=true";
+// the whole list is tens of megabytes, give a slow connection time to get it
+// all but don't wait forever on a server that has stopped sending
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// The parts of a radio-browser station we search on or keep. Field names are
+// radio-browser's own so its json dumps and search results can be read as
+// they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DumpStation {
+    stationuuid: String,
+    name: String,
+    url: String,
+    url_resolved: String,
+    homepage: String,
+    favicon: String,
+    tags: String,
+    country: String,
+    countrycode: String,
+    language: String,
+    codec: String,
+    bitrate: u32,
+    votes: i64,
+    clickcount: i64,
+    lastchangetime: String,
+    // missing from some dumps, which then count as working
+    lastcheckok: Option<u8>,
+}
+
+impl DumpStation {
+    pub fn to_station(&self) -> Station {
+        let url = if self.url_resolved.is_empty() {
+            &self.url
+        } else {
+            &self.url_resolved
+        };
+        Station {
+            name: self.name.trim().to_string(),
+            url: url.to_string(),
+            uuid: search::non_empty(&self.stationuuid),
+            tags: search::split_tags(&self.tags),
+            country: search::non_empty(&self.country),
+            language: search::non_empty(&self.language),
+            codec: search::non_empty(&self.codec),
+            bitrate: (self.bitrate > 0).then_some(self.bitrate),
+            homepage: search::non_empty(&self.homepage),
+            favicon: search::non_empty(&self.favicon),
+            ..Default::default()
+        }
+    }
+
+    fn matches(&self, filters: &Filters) -> bool {
+        let contains = |value: &str, filter: &str| {
+            let filter = filter.trim().to_lowercase();
+            filter.is_empty() || value.to_lowercase().contains(&filter)
+        };
+        let equals = |value: &str, filter: &str| {
+            let filter = filter.trim();
+            filter.is_empty() || value.eq_ignore_ascii_case(filter)
+        };
+        contains(&self.tags, &filters.tag)
+            && contains(&self.country, &filters.country)
+            && equals(&self.countrycode, &filters.countrycode)
+            && contains(&self.language, &filters.language)
+            && equals(&self.codec, &filters.codec)
+            && self.bitrate >= filters.min_bitrate as u32
+    }
+}
+
+// what gets written to the cache dir
+#[derive(Serialize, Deserialize)]
+struct Dump {
+    timestamp: i64,
+    stations: Vec<DumpStation>,
+}
+
+// A local copy of every station radio-browser knows about, with a word index
+// over name, tags and country so searching it is instant
+#[derive(Debug)]
+pub struct Mirror {
+    // unix time the copy was made
+    pub timestamp: i64,
+    stations: Vec<DumpStation>,
+    // every word to the stations it appears in, sorted so prefixes can be
+    // looked up as a range
+    index: BTreeMap<String, Vec<u32>>,
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+async fn download_stations(
+    client: &reqwest::Client,
+    url: &str,
+) -> reqwest::Result<Vec<DumpStation>> {
+    client
+        .get(url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+fn cache_path() -> PathBuf {
+    saver::get_cache_dir().join("radio-browser.json")
+}
+
+impl Mirror {
+    fn new(timestamp: i64, mut stations: Vec<DumpStation>) -> Self {
+        // the same as hidebroken for online searches
+        stations.retain(|station| station.lastcheckok != Some(0));
+        let mut index: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (position, station) in stations.iter().enumerate() {
+            let mut seen = HashSet::new();
+            for word in words(&station.name)
+                .chain(words(&station.tags))
+                .chain(words(&station.country))
+            {
+                if seen.insert(word.clone()) {
+                    index.entry(word).or_default().push(position as u32);
+                }
+            }
+        }
+        Self {
+            timestamp,
+            stations,
+            index,
+        }
+    }
+
+    pub fn station_count(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        now() - self.timestamp > REFRESH_SECONDS
+    }
+
+    // The copy made last time, if there is one
+    pub fn load_cached() -> Result<Option<Self>, Error> {
+        let data = match fs::read_to_string(cache_path()) {
+            Ok(data) => data,
+            Err(_) => return Ok(None),
+        };
+        let dump: Dump = serde_json::from_str(&data).map_err(|e| Error::Mirror(e.to_string()))?;
+        Ok(Some(Self::new(dump.timestamp, dump.stations)))
+    }
+
+    // A json dump of radio-browser stations, as served by /json/stations.
+    // Only replaces the cached copy when asked to keep it.
+    pub fn load_file(path: &Path, keep: bool) -> Result<Self, Error> {
+        let data = fs::read_to_string(path).map_err(|e| Error::Mirror(e.to_string()))?;
+        let stations: Vec<DumpStation> =
+            serde_json::from_str(&data).map_err(|e| Error::Mirror(e.to_string()))?;
+        if keep {
+            Self::save(now(), stations)
+        } else {
+            Ok(Self::new(now(), stations))
+        }
+    }
+
+    // From the servers picked in the preferences in order, or any
+    // radio-browser server if there are none
+    pub async fn download(servers: Vec<String>) -> Result<Self, Error> {
+        let client = search::client().map_err(|e| Error::Mirror(e.to_string()))?;
+        let urls: Vec<String> = if servers.is_empty() {
+            vec![DUMP_URL.to_string()]
+        } else {
+            servers
+                .iter()
+                .map(|server| format!("{server}/json/stations?hidebroken=true"))
+                .collect()
+        };
+        let mut errors = vec![];
+        for url in urls {
+            match download_stations(&client, &url).await {
+                Ok(stations) => return Self::save(now(), stations),
+                Err(error) => errors.push(format!("{url}: {error}")),
+            }
+        }
+        Err(Error::Mirror(errors.join(", ")))
+    }
+
+    fn save(timestamp: i64, stations: Vec<DumpStation>) -> Result<Self, Error> {
+        let dump = Dump {
+            timestamp,
+            stations,
+        };
+        let path = cache_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string(&dump)?)?;
+        Ok(Self::new(dump.timestamp, dump.stations))
+    }
+
+    // Every word of the query has to start a word somewhere in the name,
+    // tags or country
+    pub fn search(&self, query: &str, filters: &Filters, offset: u32) -> Vec<Station> {
+        // kept in dump order, so stations that tie on the sort order always
+        // come out the same way round and paging doesn't repeat or skip any
+        let mut matches: Option<BTreeSet<u32>> = None;
+        for word in words(query) {
+            let found: BTreeSet<u32> = self
+                .index
+                .range(word.clone()..)
+                .take_while(|(indexed, _)| indexed.starts_with(&word))
+                .flat_map(|(_, positions)| positions.iter().copied())
+                .collect();
+            matches = Some(match matches {
+                Some(matches) => matches.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        let mut results: Vec<&DumpStation> = match matches {
+            Some(matches) => matches
+                .into_iter()
+                .map(|position| &self.stations[position as usize])
+                .collect(),
+            None => self.stations.iter().collect(),
+        };
+        results.retain(|station| station.matches(filters));
+        match filters.order {
+            Order::Clicks => results.sort_by_key(|station| std::cmp::Reverse(station.clickcount)),
+            Order::Votes => results.sort_by_key(|station| std::cmp::Reverse(station.votes)),
+            Order::Bitrate => results.sort_by_key(|station| std::cmp::Reverse(station.bitrate)),
+            Order::RecentlyChanged => {
+                results.sort_by(|a, b| b.lastchangetime.cmp(&a.lastchangetime))
+            }
+        }
+        results
+            .into_iter()
+            .skip(offset as usize)
+            .take(search::PAGE_SIZE as usize)
+            .map(DumpStation::to_station)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a few stations as they come out of /json/stations
+    const DUMP: &str = r#"[
+        {"stationuuid": "a", "name": "Smooth Jazz London", "url": "http://a", "tags": "jazz,smooth",
+         "country": "The United Kingdom", "countrycode": "GB", "codec": "MP3", "bitrate": 128,
+         "votes": 10, "clickcount": 300, "lastchangetime": "2024-01-01 10:00:00", "lastcheckok": 1},
+        {"stationuuid": "b", "name": "Jazz Paris", "url": "http://b", "url_resolved": "http://b/live",
+         "tags": "jazz", "country": "France", "countrycode": "FR", "codec": "AAC", "bitrate": 64,
+         "votes": 50, "clickcount": 100, "lastchangetime": "2024-03-01 10:00:00"},
+        {"stationuuid": "c", "name": "London Talk", "url": "http://c", "tags": "news,talk",
+         "country": "The United Kingdom", "countrycode": "GB", "codec": "mp3", "bitrate": 320,
+         "votes": 5, "clickcount": 200, "lastchangetime": "2023-06-01 10:00:00", "lastcheckok": 1},
+        {"stationuuid": "d", "name": "Broken Jazz", "url": "http://d", "tags": "jazz",
+         "votes": 1000, "clickcount": 1000, "lastcheckok": 0}
+    ]"#;
+
+    fn mirror() -> Mirror {
+        Mirror::new(0, serde_json::from_str(DUMP).unwrap())
+    }
+
+    fn uuids(stations: &[Station]) -> Vec<&str> {
+        stations
+            .iter()
+            .map(|station| station.uuid.as_deref().unwrap_or_default())
+            .collect()
+    }
+
+    fn search(query: &str, filters: Filters) -> Vec<String> {
+        uuids(&mirror().search(query, &filters, 0))
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn broken_stations_are_dropped() {
+        assert_eq!(mirror().station_count(), 3);
+        assert_eq!(search("broken", Filters::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn every_word_must_start_a_word() {
+        assert_eq!(search("jaz lon", Filters::default()), ["a"]);
+        assert_eq!(search("LONDON", Filters::default()), ["a", "c"]);
+        // the country is indexed too
+        assert_eq!(search("fra", Filters::default()), ["b"]);
+        // only the start of a word counts
+        assert_eq!(search("azz", Filters::default()), Vec::<String>::new());
+        assert_eq!(search("", Filters::default()), ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn filters() {
+        let filter = |filters: Filters| search("", filters);
+        assert_eq!(
+            filter(Filters {
+                tag: "talk".to_string(),
+                ..Default::default()
+            }),
+            ["c"]
+        );
+        assert_eq!(
+            filter(Filters {
+                countrycode: "gb".to_string(),
+                codec: "mp3".to_string(),
+                ..Default::default()
+            }),
+            ["a", "c"]
+        );
+        assert_eq!(
+            filter(Filters {
+                country: "france".to_string(),
+                ..Default::default()
+            }),
+            ["b"]
+        );
+        assert_eq!(
+            filter(Filters {
+                min_bitrate: 128,
+                ..Default::default()
+            }),
+            ["a", "c"]
+        );
+    }
+
+    #[test]
+    fn orders() {
+        let order = |order: Order| {
+            search(
+                "",
+                Filters {
+                    order,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(order(Order::Clicks), ["a", "c", "b"]);
+        assert_eq!(order(Order::Votes), ["b", "a", "c"]);
+        assert_eq!(order(Order::Bitrate), ["c", "a", "b"]);
+        assert_eq!(order(Order::RecentlyChanged), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn pages() {
+        let stations = (0..120)
+            .map(|number| DumpStation {
+                stationuuid: number.to_string(),
+                name: format!("Station {number}"),
+                url: format!("http://{number}"),
+                clickcount: 1000 - number,
+                ..Default::default()
+            })
+            .collect();
+        let mirror = Mirror::new(0, stations);
+        let page = |offset| mirror.search("station", &Filters::default(), offset);
+        assert_eq!(page(0).len(), search::PAGE_SIZE as usize);
+        assert_eq!(uuids(&page(0))[0], "0");
+        assert_eq!(uuids(&page(search::PAGE_SIZE))[0], "50");
+        assert_eq!(page(100).len(), 20);
+        assert!(page(120).is_empty());
+    }
+
+    #[test]
+    fn ties_page_the_same_way_every_time() {
+        let stations = (0..80)
+            .map(|number| DumpStation {
+                stationuuid: number.to_string(),
+                name: format!("Station {number}"),
+                url: format!("http://{number}"),
+                clickcount: 10,
+                ..Default::default()
+            })
+            .collect();
+        let mirror = Mirror::new(0, stations);
+        let mut found = mirror.search("station", &Filters::default(), 0);
+        found.extend(mirror.search("station", &Filters::default(), search::PAGE_SIZE));
+        let expected: Vec<String> = (0..80).map(|number| number.to_string()).collect();
+        assert_eq!(uuids(&found), expected);
+    }
+
+    #[test]
+    fn resolved_url_is_preferred() {
+        let stations = mirror().search("paris", &Filters::default(), 0);
+        assert_eq!(stations[0].url, "http://b/live");
+        assert_eq!(stations[0].codec.as_deref(), Some("AAC"));
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use relm4::Sender;
+use zbus::{
+    Connection, connection, interface,
+    object_server::InterfaceRef,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+use crate::{Msg, Station, streamer::NowPlaying};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.relmyvibes";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+// org.mpris.MediaPlayer2
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "RelmyVibes".to_string()
+    }
+
+    #[zbus(property)]
+    fn desktop_entry(&self) -> String {
+        "uk.bezmuth.RelmyVibes".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+// org.mpris.MediaPlayer2.Player, everything the bus asks for is forwarded to
+// Radio::update as a Msg so the ui and the bus never disagree
+struct Player {
+    sender: Sender<Msg>,
+    playing: bool,
+    // the last station we played, so Play can resume it
+    station: Option<(Station, usize)>,
+    now_playing: Option<NowPlaying>,
+    volume: f64,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {}
+
+    fn previous(&self) {}
+
+    // radio streams cant be paused, so pausing just stops
+    fn pause(&self) {
+        self.sender.emit(Msg::Stop);
+    }
+
+    fn play_pause(&self) {
+        if self.playing {
+            self.sender.emit(Msg::Stop);
+        } else {
+            self.play();
+        }
+    }
+
+    fn stop(&self) {
+        self.sender.emit(Msg::Stop);
+    }
+
+    fn play(&self) {
+        if let Some((station, id)) = &self.station {
+            self.sender.emit(Msg::Play(station.clone(), *id));
+        }
+    }
+
+    fn seek(&self, _offset: i64) {}
+
+    fn set_position(&self, _track_id: ObjectPath<'_>, _position: i64) {}
+
+    fn open_uri(&self, _uri: String) {}
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        if self.playing { "Playing" } else { "Stopped" }.to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let mut metadata = HashMap::new();
+        if let Some((station, id)) = &self.station {
+            let track_id = ObjectPath::try_from(format!("/uk/bezmuth/RelmyVibes/Station/{id}"))
+                .expect("station ids are always valid object path elements");
+            metadata.insert("mpris:trackid".to_string(), owned(Value::from(track_id)));
+            // the station stands in for the album, the stream title (if the
+            // station sends one) for the track
+            metadata.insert(
+                "xesam:album".to_string(),
+                owned(Value::from(station.name.clone())),
+            );
+            let title = match &self.now_playing {
+                Some(now_playing) => now_playing.title.clone(),
+                None => station.name.clone(),
+            };
+            metadata.insert("xesam:title".to_string(), owned(Value::from(title)));
+            if let Some(artist) = self.now_playing.as_ref().and_then(|n| n.artist.clone()) {
+                metadata.insert("xesam:artist".to_string(), owned(Value::from(vec![artist])));
+            }
+            metadata.insert(
+                "xesam:url".to_string(),
+                owned(Value::from(station.url.clone())),
+            );
+        }
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) {
+        self.sender.emit(Msg::ChangeVolume(volume.clamp(0.0, 1.0)));
+    }
+
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.station.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn owned(value: Value<'_>) -> OwnedValue {
+    value
+        .try_to_owned()
+        .expect("metadata values never contain file descriptors")
+}
+
+#[derive(Debug)]
+pub struct Mpris {
+    connection: Connection,
+}
+
+impl Mpris {
+    pub async fn new(sender: Sender<Msg>) -> zbus::Result<Self> {
+        Self::serve(connection::Builder::session()?.build().await?, sender).await
+    }
+
+    // Export the interfaces on an already open connection, this lets the
+    // interface be exercised against a private dbus-daemon
+    pub async fn serve(connection: Connection, sender: Sender<Msg>) -> zbus::Result<Self> {
+        connection.object_server().at(OBJECT_PATH, Root).await?;
+        connection
+            .object_server()
+            .at(
+                OBJECT_PATH,
+                Player {
+                    sender,
+                    playing: false,
+                    station: None,
+                    now_playing: None,
+                    volume: 1.0,
+                },
+            )
+            .await?;
+        connection.request_name(BUS_NAME).await?;
+        Ok(Self { connection })
+    }
+
+    async fn player(&self) -> zbus::Result<InterfaceRef<Player>> {
+        self.connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)
+            .await
+    }
+
+    pub async fn playing(&self, station: Station, id: usize) -> zbus::Result<()> {
+        let iface = self.player().await?;
+        let mut player = iface.get_mut().await;
+        player.playing = true;
+        player.station = Some((station, id));
+        player.now_playing = None;
+        player
+            .playback_status_changed(iface.signal_emitter())
+            .await?;
+        player.metadata_changed(iface.signal_emitter()).await?;
+        player.can_play_changed(iface.signal_emitter()).await
+    }
+
+    pub async fn stopped(&self) -> zbus::Result<()> {
+        let iface = self.player().await?;
+        let mut player = iface.get_mut().await;
+        player.playing = false;
+        player.playback_status_changed(iface.signal_emitter()).await
+    }
+
+    pub async fn now_playing(&self, now_playing: Option<NowPlaying>) -> zbus::Result<()> {
+        let iface = self.player().await?;
+        let mut player = iface.get_mut().await;
+        player.now_playing = now_playing;
+        player.metadata_changed(iface.signal_emitter()).await
+    }
+
+    pub async fn volume_changed(&self, volume: f64) -> zbus::Result<()> {
+        let iface = self.player().await?;
+        let mut player = iface.get_mut().await;
+        player.volume = volume;
+        player.volume_changed(iface.signal_emitter()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    use zbus::proxy::{Builder, CacheProperties};
+
+    use super::*;
+
+    // A private session bus that goes away with the test
+    struct DbusDaemon(Child);
+
+    impl DbusDaemon {
+        // None when there is no dbus-daemon to run
+        fn start() -> Option<(Self, String)> {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(child.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some((Self(child), address.trim().to_string()))
+        }
+    }
+
+    impl Drop for DbusDaemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    async fn connect(address: &str) -> Connection {
+        connection::Builder::address(address)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn player_over_dbus() {
+        let Some((_daemon, address)) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        zbus::block_on(async {
+            let (sender, receiver) = relm4::channel::<Msg>();
+            let mpris = Mpris::serve(connect(&address).await, sender).await.unwrap();
+            let client = connect(&address).await;
+            let player = Builder::<zbus::Proxy>::new(&client)
+                .destination(BUS_NAME)
+                .unwrap()
+                .path(OBJECT_PATH)
+                .unwrap()
+                .interface("org.mpris.MediaPlayer2.Player")
+                .unwrap()
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await
+                .unwrap();
+
+            let status: String = player.get_property("PlaybackStatus").await.unwrap();
+            assert_eq!(status, "Stopped");
+
+            let station = Station {
+                name: "Test FM".to_string(),
+                url: "http://example.com/stream".to_string(),
+                ..Default::default()
+            };
+            mpris.playing(station, 3).await.unwrap();
+            mpris
+                .now_playing(NowPlaying::parse("Artist - Song"))
+                .await
+                .unwrap();
+            mpris.volume_changed(0.5).await.unwrap();
+
+            let status: String = player.get_property("PlaybackStatus").await.unwrap();
+            assert_eq!(status, "Playing");
+            let metadata: HashMap<String, OwnedValue> =
+                player.get_property("Metadata").await.unwrap();
+            let string = |key: &str| String::try_from(metadata[key].try_clone().unwrap()).unwrap();
+            assert_eq!(string("xesam:title"), "Song");
+            assert_eq!(string("xesam:album"), "Test FM");
+            assert_eq!(string("xesam:url"), "http://example.com/stream");
+            let volume: f64 = player.get_property("Volume").await.unwrap();
+            assert_eq!(volume, 0.5);
+
+            // everything from the bus turns up as a Msg
+            player.call_method("Stop", &()).await.unwrap();
+            assert!(matches!(receiver.recv().await, Some(Msg::Stop)));
+            player.call_method("Play", &()).await.unwrap();
+            assert!(matches!(
+                receiver.recv().await,
+                Some(Msg::Play(station, 3)) if station.name == "Test FM"
+            ));
+            player.set_property("Volume", 0.25).await.unwrap();
+            assert!(matches!(
+                receiver.recv().await,
+                Some(Msg::ChangeVolume(volume)) if volume == 0.25
+            ));
+        });
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use quick_xml::{Reader, escape::resolve_predefined_entity, events::Event};
+
+use crate::{Error, Station};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Format::M3u),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            _ => None,
+        }
+    }
+
+    // Guess from the contents, for when all we have is a body
+    pub fn sniff(data: &str) -> Option<Self> {
+        let start = strip_bom(data).trim_start();
+        if start.starts_with("#EXTM3U") {
+            Some(Format::M3u)
+        } else if start.to_lowercase().starts_with("[playlist]") {
+            Some(Format::Pls)
+        } else if start.starts_with("<?xml") && start.contains("<playlist") {
+            Some(Format::Xspf)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn parse(format: Format, data: &str) -> Vec<Station> {
+    let data = strip_bom(data);
+    match format {
+        Format::M3u => parse_m3u(data),
+        Format::Pls => parse_pls(data),
+        Format::Xspf => parse_xspf(data),
+    }
+}
+
+pub fn write(format: Format, stations: &[Station]) -> String {
+    match format {
+        Format::M3u => write_m3u(stations),
+        Format::Pls => write_pls(stations),
+        Format::Xspf => write_xspf(stations),
+    }
+}
+
+pub fn import(path: &Path) -> Result<Vec<Station>, Error> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| Error::Playlist(format!("{} is not a playlist", path.display())))?;
+    let data = fs::read_to_string(path).map_err(|e| Error::Playlist(e.to_string()))?;
+    Ok(parse(format, &data))
+}
+
+// Anything without a known extension gets written as m3u
+pub fn export(path: &Path, stations: &[Station]) -> Result<(), Error> {
+    let format = Format::from_path(path).unwrap_or(Format::M3u);
+    fs::write(path, write(format, stations)).map_err(|e| Error::Playlist(e.to_string()))
+}
+
+// some editors on windows start utf-8 files with a byte order mark
+fn strip_bom(data: &str) -> &str {
+    data.strip_prefix('\u{feff}').unwrap_or(data)
+}
+
+fn station(name: Option<String>, url: &str) -> Station {
+    Station {
+        name: name
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| url.to_string()),
+        url: url.to_string(),
+        ..Default::default()
+    }
+}
+
+// #EXTINF:-1,Station name
+// http://example.com/stream
+fn parse_m3u(data: &str) -> Vec<Station> {
+    let mut stations = vec![];
+    let mut name = None;
+    for line in data.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            name = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            stations.push(station(name.take(), line));
+        }
+    }
+    stations
+}
+
+fn write_m3u(stations: &[Station]) -> String {
+    let mut data = String::from("#EXTM3U\n");
+    for station in stations {
+        data.push_str(&format!("#EXTINF:-1,{}\n{}\n", station.name, station.url));
+    }
+    data
+}
+
+// [playlist]
+// File1=http://example.com/stream
+// Title1=Station name
+fn parse_pls(data: &str) -> Vec<Station> {
+    let mut entries: BTreeMap<u32, (Option<String>, Option<String>)> = BTreeMap::new();
+    for line in data.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        if let Some(number) = key.strip_prefix("file").and_then(|n| n.parse().ok()) {
+            entries.entry(number).or_default().0 = Some(value);
+        } else if let Some(number) = key.strip_prefix("title").and_then(|n| n.parse().ok()) {
+            entries.entry(number).or_default().1 = Some(value);
+        }
+    }
+    entries
+        .into_values()
+        .filter_map(|(url, name)| url.map(|url| station(name, &url)))
+        .collect()
+}
+
+fn write_pls(stations: &[Station]) -> String {
+    let mut data = String::from("[playlist]\n");
+    for (number, station) in stations.iter().enumerate() {
+        let number = number + 1;
+        data.push_str(&format!(
+            "File{number}={}\nTitle{number}={}\nLength{number}=-1\n",
+            station.url, station.name
+        ));
+    }
+    data.push_str(&format!("NumberOfEntries={}\nVersion=2\n", stations.len()));
+    data
+}
+
+// Only <track> elements and their <location> and <title> matter to us
+fn parse_xspf(data: &str) -> Vec<Station> {
+    let mut reader = Reader::from_str(data);
+    let mut stations = vec![];
+    // location and title of the track we are in, if we are in one
+    let mut track: Option<(Option<String>, Option<String>)> = None;
+    // the element inside the track whose text we are collecting
+    let mut field: Option<(Vec<u8>, String)> = None;
+    loop {
+        let text = match reader.read_event() {
+            Ok(Event::Start(element)) => {
+                match element.local_name().as_ref() {
+                    b"track" => track = Some((None, None)),
+                    name if track.is_some() => field = Some((name.to_vec(), String::new())),
+                    _ => {}
+                }
+                continue;
+            }
+            Ok(Event::End(element)) => {
+                match (element.local_name().as_ref(), &mut track, field.take()) {
+                    (b"track", Some(_), _) => {
+                        if let Some((Some(url), name)) = track.take() {
+                            stations.push(station(name, url.trim()));
+                        }
+                    }
+                    (b"location", Some((location, _)), Some((_, text))) => {
+                        *location = Some(text);
+                    }
+                    (b"title", Some((_, title)), Some((_, text))) => {
+                        *title = Some(text.trim().to_string());
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Ok(Event::Text(text)) => text.decode().map(|text| text.into_owned()).ok(),
+            Ok(Event::CData(text)) => text.decode().map(|text| text.into_owned()).ok(),
+            Ok(Event::GeneralRef(reference)) => match reference.resolve_char_ref() {
+                Ok(Some(character)) => Some(character.to_string()),
+                _ => reference
+                    .decode()
+                    .ok()
+                    .and_then(|name| resolve_predefined_entity(&name))
+                    .map(str::to_string),
+            },
+            // whatever made sense before a syntax error is still worth having
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        if let (Some((_, collected)), Some(text)) = (&mut field, text) {
+            collected.push_str(&text);
+        }
+    }
+    stations
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn write_xspf(stations: &[Station]) -> String {
+    let mut data = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for station in stations {
+        data.push_str(&format!(
+            "    <track>\n      <location>{}</location>\n      <title>{}</title>\n    </track>\n",
+            xml_escape(&station.url),
+            xml_escape(&station.name)
+        ));
+    }
+    data.push_str("  </trackList>\n</playlist>\n");
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations() -> Vec<Station> {
+        vec![
+            station(
+                Some("Rock & Roll <Live>".to_string()),
+                "http://example.com/rock?a=1&b=2",
+            ),
+            station(
+                Some("Jazz \"FM\"".to_string()),
+                "https://example.com/jazz.mp3",
+            ),
+        ]
+    }
+
+    fn names_and_urls(stations: &[Station]) -> Vec<(String, String)> {
+        stations
+            .iter()
+            .map(|station| (station.name.clone(), station.url.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        for format in [Format::M3u, Format::Pls, Format::Xspf] {
+            let data = write(format, &stations());
+            assert_eq!(Format::sniff(&data), Some(format));
+            assert_eq!(
+                names_and_urls(&parse(format, &data)),
+                names_and_urls(&stations()),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let data = "\u{feff}#EXTM3U\n#EXTINF:-1,One\nhttp://one\n";
+        assert_eq!(Format::sniff(data), Some(Format::M3u));
+        let stations = parse(Format::M3u, data);
+        assert_eq!(
+            names_and_urls(&stations),
+            [("One".into(), "http://one".into())]
+        );
+
+        let data = "\u{feff}[playlist]\nFile1=http://one\n";
+        assert_eq!(Format::sniff(data), Some(Format::Pls));
+        assert_eq!(parse(Format::Pls, data)[0].url, "http://one");
+    }
+
+    #[test]
+    fn m3u_without_names() {
+        let stations = parse(Format::M3u, "http://one\n\n# comment\nhttp://two\n");
+        assert_eq!(
+            names_and_urls(&stations),
+            [
+                ("http://one".into(), "http://one".into()),
+                ("http://two".into(), "http://two".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_in_any_order() {
+        let stations = parse(
+            Format::Pls,
+            "[playlist]\nTitle2=Two\nFile2=http://two\nfile1=http://one\nNumberOfEntries=2\n",
+        );
+        assert_eq!(
+            names_and_urls(&stations),
+            [
+                ("http://one".into(), "http://one".into()),
+                ("Two".into(), "http://two".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn xspf_entities_and_cdata() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track>
+      <title>Caf&#233; &amp; Bar &#x2013; Live</title>
+      <location>http://example.com/a?x=1&amp;y=2</location>
+    </track>
+    <track>
+      <location><![CDATA[http://example.com/b?x=1&y=2]]></location>
+      <title><![CDATA[<Big> & Loud]]></title>
+      <annotation>ignored</annotation>
+    </track>
+    <track>
+      <title>No location</title>
+    </track>
+  </trackList>
+</playlist>"#;
+        assert_eq!(
+            names_and_urls(&parse(Format::Xspf, data)),
+            [
+                (
+                    "Café & Bar – Live".into(),
+                    "http://example.com/a?x=1&y=2".into()
+                ),
+                ("<Big> & Loud".into(), "http://example.com/b?x=1&y=2".into())
+            ]
+        );
+    }
+}
@@ -0,0 +1,486 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer::prelude::*;
+use relm4::AsyncComponentSender;
+
+use crate::{Error, Station};
+
+// How the stream can be cut into files that still play on their own
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Container {
+    // self-synchronising frames, any frame header is a good place to start.
+    // The frame sync is the first byte plus the second masked with .0
+    // matching .1
+    Frames(u8, u8),
+    // pages, with the stream headers repeated at the start of every file
+    Ogg,
+    // anything else is recorded from the first byte into one file
+    #[default]
+    Other,
+}
+
+fn container(caps: &gst::CapsRef) -> Container {
+    let Some(structure) = caps.structure(0) else {
+        return Container::Other;
+    };
+    match structure.name().as_str() {
+        "audio/mpeg" => match structure.get::<i32>("mpegversion") {
+            Ok(1) => Container::Frames(0xe0, 0xe0),
+            // adts
+            _ => Container::Frames(0xf6, 0xf0),
+        },
+        "audio/aac" => Container::Frames(0xf6, 0xf0),
+        "application/ogg" | "audio/ogg" => Container::Ogg,
+        _ => Container::Other,
+    }
+}
+
+fn extension(caps: &gst::CapsRef) -> &'static str {
+    let Some(structure) = caps.structure(0) else {
+        return "audio";
+    };
+    match structure.name().as_str() {
+        "audio/mpeg" => match structure.get::<i32>("mpegversion") {
+            Ok(1) => "mp3",
+            _ => "aac",
+        },
+        "application/ogg" | "audio/ogg" => "ogg",
+        "audio/x-flac" => "flac",
+        "audio/aac" => "aac",
+        "audio/x-wav" => "wav",
+        _ => "audio",
+    }
+}
+
+// Where the first frame starts in data, if one does
+fn frame_start(data: &[u8], mask: u8, sync: u8) -> Option<usize> {
+    data.windows(2)
+        .position(|bytes| bytes[0] == 0xff && bytes[1] & mask == sync)
+}
+
+// Cuts an ogg stream into whole pages, keeping hold of the header pages of
+// the current logical stream so a file can be started at any page
+#[derive(Debug, Default)]
+struct OggPages {
+    // the start of a page that hasn't all arrived yet
+    partial: Vec<u8>,
+    headers: Vec<u8>,
+    // still collecting headers, the pages so far aren't audio
+    in_headers: bool,
+}
+
+struct Page {
+    data: Vec<u8>,
+    // beginning of a logical stream, the first page of a new file in a
+    // chained stream
+    first: bool,
+    // one of the header pages, already kept in headers
+    header: bool,
+}
+
+impl OggPages {
+    // The next whole page, once push has been given enough of it
+    fn next_page(&mut self) -> Option<Page> {
+        // skip anything that isn't a page, ogg is made to be resynced like this
+        let Some(start) = self.partial.windows(4).position(|bytes| bytes == b"OggS") else {
+            let keep = self.partial.len().min(3);
+            self.partial.drain(..self.partial.len() - keep);
+            return None;
+        };
+        self.partial.drain(..start);
+        // 27 bytes of header, then a table of segment sizes
+        let segments = *self.partial.get(26)? as usize;
+        let table = self.partial.get(27..27 + segments)?;
+        let size = 27 + segments + table.iter().map(|&size| size as usize).sum::<usize>();
+        if self.partial.len() < size {
+            return None;
+        }
+        let data: Vec<u8> = self.partial.drain(..size).collect();
+        let first = data[5] & 0x02 != 0;
+        let granule = u64::from_le_bytes(data[6..14].try_into().unwrap_or_default());
+        if first {
+            self.headers.clear();
+            self.in_headers = true;
+        }
+        // header packets have no position in the audio
+        self.in_headers &= granule == 0;
+        if self.in_headers {
+            self.headers.extend_from_slice(&data);
+        }
+        Some(Page {
+            data,
+            first,
+            header: self.in_headers,
+        })
+    }
+}
+
+// The file being written. It's only opened at the first place the stream can
+// be cut, so recording started mid-stream or split at a new title never
+// begins half way through a frame.
+#[derive(Debug)]
+struct Output {
+    directory: PathBuf,
+    station: String,
+    title: Option<String>,
+    file: Option<File>,
+    // waiting for somewhere to start the next file
+    waiting: bool,
+    sender: relm4::Sender<crate::Msg>,
+}
+
+impl Output {
+    fn write(&mut self, extension: &str, data: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() {
+            fs::create_dir_all(&self.directory)?;
+            self.file = Some(File::create(
+                self.directory.join(self.file_name(extension)),
+            )?);
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    // Bytes from before the cut belong to the file being split off, if there
+    // is one
+    fn write_pending(&mut self, extension: &str, data: &[u8]) -> std::io::Result<()> {
+        match self.file {
+            Some(_) => self.write(extension, data),
+            None => Ok(()),
+        }
+    }
+
+    fn file_name(&self, extension: &str) -> String {
+        let time = glib::DateTime::now_local()
+            .and_then(|time| time.format("%F %H.%M.%S"))
+            .map(|time| time.to_string())
+            .unwrap_or_default();
+        let name = match &self.title {
+            Some(title) => format!("{} - {time} - {title}", self.station),
+            None => format!("{} - {time}", self.station),
+        };
+        // a slash in a station or track name would point somewhere else entirely
+        format!("{}.{extension}", name.replace('/', "_"))
+    }
+}
+
+#[derive(Debug, Default)]
+struct TapState {
+    // the pad in the player's pipeline the stream is taken from
+    pad: Option<glib::WeakRef<gst::Pad>>,
+    container: Container,
+    extension: &'static str,
+    ogg: OggPages,
+    output: Option<Output>,
+}
+
+impl TapState {
+    fn feed(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let extension = self.extension;
+        match self.container {
+            Container::Ogg => {
+                // pages are tracked even when not recording so the headers
+                // are there for when a recording starts
+                self.ogg.partial.extend_from_slice(data);
+                while let Some(page) = self.ogg.next_page() {
+                    let Some(output) = &mut self.output else {
+                        continue;
+                    };
+                    if output.waiting {
+                        if page.first {
+                            output.file = None;
+                        } else if page.header {
+                            // these end up in the headers for the next file
+                            continue;
+                        } else {
+                            output.file = None;
+                            output.write(extension, &self.ogg.headers)?;
+                        }
+                        output.waiting = false;
+                    }
+                    output.write(extension, &page.data)?;
+                }
+            }
+            Container::Frames(mask, sync) => {
+                let Some(output) = &mut self.output else {
+                    return Ok(());
+                };
+                let mut data = data;
+                if output.waiting {
+                    let Some(start) = frame_start(data, mask, sync) else {
+                        return output.write_pending(extension, data);
+                    };
+                    output.write_pending(extension, &data[..start])?;
+                    output.file = None;
+                    output.waiting = false;
+                    data = &data[start..];
+                }
+                output.write(extension, data)?;
+            }
+            Container::Other => {
+                if let Some(output) = &mut self.output {
+                    output.waiting = false;
+                    output.write(extension, data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Sits on the player's pipeline and copies what the station sends, after ICY
+// metadata has been taken out but before anything is decoded, to the
+// recording if there is one. Recording this way needs no second connection
+// and the file is exactly what is playing.
+#[derive(Debug, Clone, Default)]
+pub struct Tap(Arc<Mutex<TapState>>);
+
+impl Tap {
+    pub fn attach(&self, pipeline: &gst::Element) {
+        // every new stream starts from scratch
+        let tap = self.clone();
+        pipeline.connect("source-setup", false, move |_| {
+            let mut state = tap.0.lock().unwrap();
+            state.pad = None;
+            state.ogg = OggPages::default();
+            None
+        });
+
+        let Some(bin) = pipeline.downcast_ref::<gst::Bin>() else {
+            return;
+        };
+        // typefind sits in front of the demuxer or parser, so its output is
+        // the stream with any ICY metadata already stripped by icydemux
+        let tap = self.clone();
+        bin.connect_deep_element_added(move |_, _, element| {
+            let is_typefind = element
+                .factory()
+                .is_some_and(|factory| factory.name() == "typefind");
+            if let Some(pad) = element.static_pad("src").filter(|_| is_typefind) {
+                let tap = tap.clone();
+                pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+                    if let Some(buffer) = info.buffer() {
+                        tap.buffer(pad, buffer);
+                    }
+                    gst::PadProbeReturn::Ok
+                });
+            }
+        });
+    }
+
+    fn buffer(&self, pad: &gst::Pad, buffer: &gst::BufferRef) {
+        let mut state = self.0.lock().unwrap();
+        match state.pad.as_ref().and_then(|claimed| claimed.upgrade()) {
+            Some(claimed) if claimed != *pad => return,
+            Some(_) => {}
+            // the first typefind to find something other than ICY is the
+            // one in front of the demuxer, later ones would see the same
+            // bytes again
+            None => {
+                let Some(caps) = pad.current_caps() else {
+                    return;
+                };
+                if caps
+                    .structure(0)
+                    .is_some_and(|structure| structure.name() == "application/x-icy")
+                {
+                    return;
+                }
+                state.pad = Some(pad.downgrade());
+                state.container = container(&caps);
+                state.extension = extension(&caps);
+            }
+        }
+        let Ok(map) = buffer.map_readable() else {
+            return;
+        };
+        if let Err(error) = state.feed(&map)
+            && let Some(output) = state.output.take()
+        {
+            output
+                .sender
+                .emit(crate::Msg::RecordingError(Error::Recording(
+                    error.to_string(),
+                )));
+        }
+    }
+}
+
+// A recording in progress, it ends when this is dropped
+#[derive(Debug)]
+pub struct Recorder {
+    tap: Tap,
+}
+
+impl Recorder {
+    // Nothing gets written until the station's stream is flowing
+    pub fn start(
+        station: &Station,
+        directory: PathBuf,
+        tap: &Tap,
+        sender: AsyncComponentSender<crate::Radio>,
+    ) -> Self {
+        tap.0.lock().unwrap().output = Some(Output {
+            directory,
+            station: station.name.clone(),
+            title: None,
+            file: None,
+            waiting: true,
+            sender: sender.input_sender().clone(),
+        });
+        Self { tap: tap.clone() }
+    }
+
+    // Start a new file named after the track that just started, at the next
+    // point the stream can be cut. Streams that can't be cut stay in one file.
+    pub fn split(&self, title: String) {
+        let mut state = self.tap.0.lock().unwrap();
+        let container = state.container;
+        if let Some(output) = &mut state.output
+            && container != Container::Other
+        {
+            output.title = Some(title);
+            output.waiting = true;
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.tap.0.lock().unwrap().output = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn page(first: bool, granule: u64, payload: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(if first { 0x02 } else { 0 });
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.push(1);
+        page.push(payload.len() as u8);
+        page.extend_from_slice(payload);
+        page
+    }
+
+    fn output(directory: &std::path::Path) -> Output {
+        Output {
+            directory: directory.to_path_buf(),
+            station: "Test".to_string(),
+            title: None,
+            file: None,
+            waiting: true,
+            sender: relm4::channel().0,
+        }
+    }
+
+    // the files written to directory, the one split off at "Next" last
+    fn files(directory: &std::path::Path) -> Vec<Vec<u8>> {
+        let mut files: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| {
+                let next = path.to_string_lossy().contains("Next");
+                (next, fs::read(path).unwrap())
+            })
+            .collect();
+        files.sort();
+        files.into_iter().map(|(_, data)| data).collect()
+    }
+
+    #[test]
+    fn ogg_pages_across_buffers() {
+        let headers = [page(true, 0, b"id"), page(false, 0, b"comment")].concat();
+        let audio = page(false, 960, b"audio");
+        let stream = [b"junk".as_slice(), &headers, &audio].concat();
+
+        let mut pages = OggPages::default();
+        let mut found = vec![];
+        // a byte at a time so every page straddles a buffer
+        for byte in stream {
+            pages.partial.push(byte);
+            while let Some(page) = pages.next_page() {
+                found.push((page.first, page.header, page.data));
+            }
+        }
+        assert_eq!(found.len(), 3);
+        assert!(found[0].0 && found[0].1);
+        assert!(!found[1].0 && found[1].1);
+        assert!(!found[2].0 && !found[2].1);
+        assert_eq!(found[2].2, audio);
+        assert_eq!(pages.headers, headers);
+    }
+
+    #[test]
+    fn ogg_recording_starts_with_the_headers() {
+        let directory = temp_dir("recorder-ogg");
+        let headers = [page(true, 0, b"id"), page(false, 0, b"comment")].concat();
+        let mut state = TapState {
+            container: Container::Ogg,
+            extension: "ogg",
+            ..Default::default()
+        };
+        state.feed(&headers).unwrap();
+        state.feed(&page(false, 960, b"one")).unwrap();
+
+        // started mid-stream, then split at a new title
+        state.output = Some(output(&directory));
+        state.feed(&page(false, 1920, b"two")).unwrap();
+        let output = state.output.as_mut().unwrap();
+        output.title = Some("Next".to_string());
+        output.waiting = true;
+        state.feed(&page(false, 2880, b"three")).unwrap();
+        state.output = None;
+
+        assert_eq!(
+            files(&directory),
+            [
+                [headers.as_slice(), &page(false, 1920, b"two")].concat(),
+                [headers.as_slice(), &page(false, 2880, b"three")].concat(),
+            ]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn mp3_files_start_at_a_frame() {
+        let directory = temp_dir("recorder-mp3");
+        let mut state = TapState {
+            container: Container::Frames(0xe0, 0xe0),
+            extension: "mp3",
+            output: Some(output(&directory)),
+            ..Default::default()
+        };
+        state.feed(&[1, 2, 0xff, 0xfb, 3]).unwrap();
+        let output = state.output.as_mut().unwrap();
+        output.title = Some("Next".to_string());
+        output.waiting = true;
+        state.feed(&[4, 0xff, 0xfb, 5]).unwrap();
+        state.output = None;
+
+        assert_eq!(
+            files(&directory),
+            [vec![0xff, 0xfb, 3, 4], vec![0xff, 0xfb, 5]]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn frame_sync() {
+        assert_eq!(frame_start(&[0, 0xff, 0xfb], 0xe0, 0xe0), Some(1));
+        assert_eq!(frame_start(&[0xff, 0x00, 0xff], 0xe0, 0xe0), None);
+        // adts
+        assert_eq!(frame_start(&[7, 0xff, 0xf1], 0xf6, 0xf0), Some(1));
+    }
+}
@@ -0,0 +1,345 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use relm4::tokio::{self, sync::Semaphore};
+use reqwest::header::CONTENT_TYPE;
+
+use crate::{
+    Error,
+    playlist::{self, Format},
+};
+
+// playlists pointing at playlists pointing at... give up eventually
+const MAX_DEPTH: usize = 5;
+// playlists and redirect pages are tiny, anything bigger than this is
+// probably the stream itself
+const MAX_BODY: usize = 64 * 1024;
+// how many urls resolve_all works on at once, so importing a long playlist
+// doesn't open a connection to every station in one go
+const MAX_CONCURRENT: usize = 8;
+
+// Follow redirects, playlists and html refresh pages until we reach something
+// that looks like an actual audio stream, and return its url
+pub async fn resolve(url: &str) -> Result<String, Error> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::limited(10))
+        .connect_timeout(Duration::from_secs(10))
+        // covers reading the body too, so a server trickling out a
+        // playlist can't hold things up forever
+        .timeout(Duration::from_secs(20))
+        .build()
+        .map_err(|e| Error::Resolve(e.to_string()))?;
+
+    let mut url = url.to_string();
+    for _ in 0..MAX_DEPTH {
+        // mms://, rtsp:// and friends are for gstreamer to deal with
+        if !is_http(&url) {
+            return Ok(url);
+        }
+        match resolve_once(&client, &url).await? {
+            Step::Stream(stream) => return Ok(stream),
+            Step::Next(next) => url = next,
+        }
+    }
+    Err(Error::Resolve(format!(
+        "too many nested playlists behind {url}"
+    )))
+}
+
+// Resolves every url, a few at a time, returning the results in the same
+// order as the urls
+pub async fn resolve_all(urls: Vec<String>) -> Vec<Result<String, Error>> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, url) in urls.into_iter().enumerate() {
+        let permits = Arc::clone(&permits);
+        tasks.spawn(async move {
+            let _permit = permits.acquire().await;
+            (index, resolve(&url).await)
+        });
+    }
+    let mut resolved = tasks.join_all().await;
+    resolved.sort_by_key(|(index, _)| *index);
+    resolved.into_iter().map(|(_, resolved)| resolved).collect()
+}
+
+enum Step {
+    // where the stream ended up after redirects
+    Stream(String),
+    // a playlist or page pointed somewhere else, look there next
+    Next(String),
+}
+
+async fn resolve_once(client: &reqwest::Client, url: &str) -> Result<Step, Error> {
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| Error::Resolve(e.to_string()))?;
+
+    let final_url = response.url().to_string();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        })
+        .unwrap_or_default();
+
+    let format = match content_type.as_str() {
+        "audio/x-mpegurl" | "audio/mpegurl" => Some(Format::M3u),
+        "audio/x-scpls" => Some(Format::Pls),
+        "application/xspf+xml" => Some(Format::Xspf),
+        _ => Format::from_path(Path::new(response.url().path())),
+    };
+    let is_html = content_type == "text/html";
+    if format.is_none() && !is_html && !content_type.starts_with("text/") {
+        return Ok(Step::Stream(final_url));
+    }
+
+    // too big for a playlist, treat it as the stream
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_BODY as u64)
+    {
+        return Ok(Step::Stream(final_url));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| Error::Resolve(e.to_string()))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_BODY {
+            // too big for a playlist, treat it as the stream
+            return Ok(Step::Stream(final_url));
+        }
+    }
+    let body = String::from_utf8_lossy(&body);
+
+    // HLS is handled by gstreamer itself, so that is as far as we go
+    if body.contains("#EXT-X-") {
+        return Ok(Step::Stream(final_url));
+    }
+
+    if is_html {
+        return refresh_url(&body)
+            .map(|next| Step::Next(join(&final_url, &next)))
+            .ok_or_else(|| Error::Resolve(format!("{final_url} is a web page, not a stream")));
+    }
+
+    let format = format
+        .or_else(|| Format::sniff(&body))
+        .ok_or_else(|| Error::Resolve(format!("{final_url} is not a stream or a playlist")))?;
+    playlist::parse(format, &body)
+        .into_iter()
+        .next()
+        .map(|station| Step::Next(join(&final_url, &station.url)))
+        .ok_or_else(|| Error::Resolve(format!("the playlist at {final_url} is empty")))
+}
+
+// <meta http-equiv="refresh" content="0; url=http://example.com/stream">
+fn refresh_url(html: &str) -> Option<String> {
+    // ascii only so byte offsets still line up with html
+    let lower = html.to_ascii_lowercase();
+    let meta = lower.find("http-equiv=\"refresh\"")?;
+    let content = meta + lower[meta..].find("url=")? + "url=".len();
+    let end = content + lower[content..].find(['"', '\'', '>'])?;
+    Some(html[content..end].trim().to_string())
+}
+
+// anything that doesn't parse is left for reqwest to complain about
+fn is_http(url: &str) -> bool {
+    match reqwest::Url::parse(url) {
+        Ok(url) => matches!(url.scheme(), "http" | "https"),
+        Err(_) => true,
+    }
+}
+
+// playlist entries are sometimes relative to the playlist itself
+fn join(base: &str, url: &str) -> String {
+    reqwest::Url::parse(base)
+        .and_then(|base| base.join(url))
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{block_on, response, serve};
+
+    fn routes(base: &str, path: &str) -> String {
+        match path {
+            "/stream" => response("200 OK", &[("Content-Type", "audio/mpeg")], "ID3"),
+            "/redirect" => response("302 Found", &[("Location", "/stream")], ""),
+            "/listen.pls" => response(
+                "200 OK",
+                &[("Content-Type", "audio/x-scpls")],
+                "[playlist]\nFile1=/stream\nTitle1=Test\n",
+            ),
+            "/listen.m3u" => response(
+                "200 OK",
+                &[("Content-Type", "audio/x-mpegurl")],
+                &format!("#EXTM3U\n#EXTINF:-1,Test\n{base}/redirect\n"),
+            ),
+            // served as plain text, only the extension says what it is
+            "/plain.m3u" => response(
+                "200 OK",
+                &[("Content-Type", "text/plain")],
+                "mms://example.com/live\n",
+            ),
+            "/page" => response(
+                "200 OK",
+                &[("Content-Type", "text/html; charset=utf-8")],
+                "<html><head><meta http-equiv=\"refresh\" content=\"0; url=/listen.pls\"></head></html>",
+            ),
+            "/about" => response(
+                "200 OK",
+                &[("Content-Type", "text/html")],
+                "<html><body>Nothing to hear here</body></html>",
+            ),
+            "/loop.m3u" => response(
+                "200 OK",
+                &[("Content-Type", "audio/x-mpegurl")],
+                "/loop.m3u\n",
+            ),
+            // claims more than we are willing to read and never sends it
+            "/huge.txt" => format!(
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY * 10
+            ),
+            _ => response("404 Not Found", &[], ""),
+        }
+    }
+
+    fn resolve_blocking(url: &str) -> Result<String, Error> {
+        block_on(resolve(url))
+    }
+
+    #[test]
+    fn direct_stream() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/stream")).unwrap(),
+            format!("{base}/stream")
+        );
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/redirect")).unwrap(),
+            format!("{base}/stream")
+        );
+    }
+
+    #[test]
+    fn pls_with_relative_entry() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/listen.pls")).unwrap(),
+            format!("{base}/stream")
+        );
+    }
+
+    #[test]
+    fn m3u_pointing_at_a_redirect() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/listen.m3u")).unwrap(),
+            format!("{base}/stream")
+        );
+    }
+
+    #[test]
+    fn html_refresh_page() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/page")).unwrap(),
+            format!("{base}/stream")
+        );
+    }
+
+    #[test]
+    fn html_without_refresh() {
+        let base = serve(routes);
+        assert!(matches!(
+            resolve_blocking(&format!("{base}/about")),
+            Err(Error::Resolve(_))
+        ));
+    }
+
+    #[test]
+    fn other_schemes_are_kept() {
+        assert_eq!(
+            resolve_blocking("mms://example.com/live").unwrap(),
+            "mms://example.com/live"
+        );
+        assert_eq!(
+            resolve_blocking("rtsp://example.com/live").unwrap(),
+            "rtsp://example.com/live"
+        );
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/plain.m3u")).unwrap(),
+            "mms://example.com/live"
+        );
+    }
+
+    #[test]
+    fn resolves_all_in_order() {
+        let base = serve(routes);
+        let paths = ["/listen.pls", "/about", "/stream", "/page", "/redirect"];
+        let urls: Vec<String> = (0..MAX_CONCURRENT * 3)
+            .map(|index| format!("{base}{}", paths[index % paths.len()]))
+            .collect();
+        let resolved = block_on(resolve_all(urls.clone()));
+        assert_eq!(resolved.len(), urls.len());
+        for (url, resolved) in urls.iter().zip(resolved) {
+            if url.ends_with("/about") {
+                assert!(resolved.is_err());
+            } else {
+                assert_eq!(resolved.unwrap(), format!("{base}/stream"));
+            }
+        }
+    }
+
+    #[test]
+    fn gives_up_on_playlist_loops() {
+        let base = serve(routes);
+        assert!(matches!(
+            resolve_blocking(&format!("{base}/loop.m3u")),
+            Err(Error::Resolve(_))
+        ));
+    }
+
+    #[test]
+    fn big_bodies_are_not_read() {
+        let base = serve(routes);
+        assert_eq!(
+            resolve_blocking(&format!("{base}/huge.txt")).unwrap(),
+            format!("{base}/huge.txt")
+        );
+    }
+
+    #[test]
+    fn refresh_urls() {
+        assert_eq!(
+            refresh_url("<META HTTP-EQUIV=\"Refresh\" CONTENT=\"5;URL=http://example.com/a\">")
+                .as_deref(),
+            Some("http://example.com/a")
+        );
+        assert_eq!(refresh_url("<p>no refresh</p>"), None);
+    }
+}
@@ -1,28 +1,362 @@
 use std::env;
-use std::fs;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-fn get_data_dir() -> String {
-    env::var("XDG_DATA_HOME")
-        .unwrap_or_else(|_| format!("~/.local/share/{}", env!("CARGO_CRATE_NAME")))
+use relm4::gtk::glib;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+// Older versions only ever wrote stations.json, everything else in the
+// data dir was added after the move
+const DATA_FILES: [&str; 1] = ["stations.json"];
+
+// $XDG_DATA_HOME/relmyvibes, glib takes care of falling back to
+// ~/.local/share when it isn't set
+fn get_data_dir() -> PathBuf {
+    glib::user_data_dir().join(env!("CARGO_CRATE_NAME"))
+}
+
+// $XDG_CACHE_HOME/relmyvibes, for things we can always download again
+pub fn get_cache_dir() -> PathBuf {
+    glib::user_cache_dir().join(env!("CARGO_CRATE_NAME"))
+}
+
+pub fn get_recordings_dir() -> PathBuf {
+    glib::user_special_dir(glib::UserDirectory::Music)
+        .map(|music| music.join("RelmyVibes"))
+        .unwrap_or_else(|| get_data_dir().join("recordings"))
+}
+
+// Older versions wrote straight into $XDG_DATA_HOME when it was set, and to
+// a literal "~/.local/share/relmyvibes" relative to the working directory
+// when it wasn't. Move anything found there into the real data dir, without
+// overwriting files that are already in place.
+pub fn migrate_old_locations() -> Result<(), crate::Error> {
+    let mut old_dirs = vec![PathBuf::from("~/.local/share").join(env!("CARGO_CRATE_NAME"))];
+    if let Some(data_home) = env::var_os("XDG_DATA_HOME") {
+        old_dirs.push(PathBuf::from(data_home));
+    }
+
+    let data_dir = get_data_dir();
+    for old_dir in old_dirs {
+        if old_dir == data_dir {
+            continue;
+        }
+        for name in DATA_FILES {
+            let old_path = old_dir.join(name);
+            let new_path = data_dir.join(name);
+            if old_path.is_file() && !new_path.exists() {
+                fs::create_dir_all(&data_dir)?;
+                move_file(&old_path, &new_path)?;
+            }
+        }
+    }
+    Ok(())
 }
 
-pub fn save_stations(stations: Vec<crate::Station>) -> std::io::Result<()> {
-    let _ = fs::create_dir(get_data_dir());
-    let mut data_file = File::create(format!("{}/stations.json", get_data_dir()))?;
-    let json = serde_json::to_string(&stations)?;
-    data_file.write_all(json.as_bytes())?;
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    // rename doesn't work across filesystems
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
     Ok(())
 }
 
-pub fn load_stations() -> Vec<crate::Station> {
-    File::open(format!("{}/stations.json", get_data_dir())).map_or_else(
-        |_| Vec::new(),
-        |mut data_file| {
-            let mut data = String::new();
-            data_file.read_to_string(&mut data).unwrap();
-            serde_json::from_str(&data).unwrap()
+// Write to a temporary file and rename it over the real one so a crash half
+// way through never leaves a truncated file behind. The previous version is
+// kept as <name>.bak.
+fn write_atomic(name: &str, contents: &[u8]) -> Result<(), crate::Error> {
+    let data_dir = get_data_dir();
+    fs::create_dir_all(&data_dir)?;
+    let path = data_dir.join(name);
+    let temp_path = data_dir.join(format!("{name}.tmp"));
+
+    let mut temp_file = File::create(&temp_path)?;
+    temp_file.write_all(contents)?;
+    temp_file.sync_all()?;
+
+    if path.is_file() {
+        fs::copy(&path, data_dir.join(format!("{name}.bak")))?;
+    }
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+fn save_json<T: Serialize + ?Sized>(name: &str, value: &T) -> Result<(), crate::Error> {
+    write_atomic(name, serde_json::to_string(value)?.as_bytes())
+}
+
+// For files that aren't worth refusing to start over, anything missing or
+// broken just starts out empty
+fn load_json<T: DeserializeOwned + Default>(name: &str) -> T {
+    fs::read_to_string(get_data_dir().join(name))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+// Bump this whenever the layout of stations.json changes, and teach
+// migrate_stations how to get from the previous version to the new one.
+// Fields added to Station with #[serde(default)] don't need a new version.
+const STATIONS_VERSION: u64 = 1;
+
+#[derive(Serialize)]
+struct StationsFile<'a> {
+    version: u64,
+    stations: &'a [crate::Station],
+    groups: &'a [String],
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StationData {
+    pub stations: Vec<crate::Station>,
+    // group names in the order they are shown, older files have none
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // read from an older version or the backup, so worth writing back
+    #[serde(skip)]
+    pub stale: bool,
+}
+
+pub fn save_stations(stations: &[crate::Station], groups: &[String]) -> Result<(), crate::Error> {
+    save_json(
+        "stations.json",
+        &StationsFile {
+            version: STATIONS_VERSION,
+            stations,
+            groups,
         },
     )
 }
+
+// Bring whatever version we found on disk up to STATIONS_VERSION
+fn migrate_stations(mut data: serde_json::Value) -> serde_json::Value {
+    loop {
+        let version = data
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        data = match version {
+            // version 0 was a bare array of stations
+            0 if data.is_array() => serde_json::json!({ "version": 1, "stations": data }),
+            _ => return data,
+        };
+    }
+}
+
+fn parse_stations(data: &str) -> serde_json::Result<StationData> {
+    let value: serde_json::Value = serde_json::from_str(data)?;
+    let version = value
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0);
+    let mut data: StationData = serde_json::from_value(migrate_stations(value))?;
+    data.stale = version < STATIONS_VERSION;
+    Ok(data)
+}
+
+// A broken stations.json gets moved out of the way (so the next save doesn't
+// destroy it) and the backup is used instead, if there is a usable one. The
+// error says what happened so it can be shown to the user.
+pub fn load_stations() -> (StationData, Option<crate::Error>) {
+    load_stations_from(&get_data_dir())
+}
+
+fn load_stations_from(data_dir: &Path) -> (StationData, Option<crate::Error>) {
+    let path = data_dir.join("stations.json");
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(_) => return (StationData::default(), None),
+    };
+    let error = match parse_stations(&data) {
+        Ok(data) => return (data, None),
+        Err(error) => error,
+    };
+
+    let timestamp = glib::DateTime::now_local()
+        .map(|now| now.to_unix())
+        .unwrap_or_default();
+    let quarantine_path = data_dir.join(format!("stations.json.corrupt-{timestamp}"));
+    if let Err(error) = move_file(&path, &quarantine_path) {
+        return (StationData::default(), Some(error.into()));
+    }
+
+    let backup = fs::read_to_string(data_dir.join("stations.json.bak"))
+        .ok()
+        .and_then(|data| parse_stations(&data).ok())
+        .map(|data| StationData {
+            stale: true,
+            ..data
+        });
+    let outcome = match backup {
+        Some(_) => "your stations were restored from the backup",
+        None => "there was no usable backup",
+    };
+    (
+        backup.unwrap_or_default(),
+        Some(crate::Error::Storage(format!(
+            "stations.json could not be read ({error}), it was moved to {} and {outcome}",
+            quarantine_path.display()
+        ))),
+    )
+}
+
+// The search cache lives in the cache dir and doesn't need the care taken
+// with the data files, losing it only costs a few requests
+pub fn save_search_cache(cache: &crate::search::Cache) -> Result<(), crate::Error> {
+    let cache_dir = get_cache_dir();
+    fs::create_dir_all(&cache_dir)?;
+    fs::write(cache_dir.join("search.json"), serde_json::to_string(cache)?)?;
+    Ok(())
+}
+
+pub fn load_search_cache() -> crate::search::Cache {
+    fs::read_to_string(get_cache_dir().join("search.json"))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_history(history: &[crate::history::HistoryEntry]) -> Result<(), crate::Error> {
+    save_json("history.json", history)
+}
+
+pub fn load_history() -> Vec<crate::history::HistoryEntry> {
+    load_json("history.json")
+}
+
+pub fn save_schedules(schedules: &[crate::scheduler::Schedule]) -> Result<(), crate::Error> {
+    save_json("schedules.json", schedules)
+}
+
+pub fn load_schedules() -> Vec<crate::scheduler::Schedule> {
+    load_json("schedules.json")
+}
+
+pub fn save_alarms(alarms: &[crate::alarm::Alarm]) -> Result<(), crate::Error> {
+    save_json("alarms.json", alarms)
+}
+
+pub fn load_alarms() -> Vec<crate::alarm::Alarm> {
+    load_json("alarms.json")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // how many tracks the song history keeps
+    pub history_limit: usize,
+    // start a new recording file whenever the stream title changes
+    pub split_recordings: bool,
+    pub search_filters: crate::search::Filters,
+    // search the local copy of radio-browser instead of going online
+    pub offline_search: bool,
+    // radio-browser servers to use, in order of preference, instead of
+    // looking them up through DNS
+    pub api_servers: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            history_limit: 500,
+            split_recordings: false,
+            search_filters: crate::search::Filters::default(),
+            offline_search: false,
+            api_servers: vec![],
+        }
+    }
+}
+
+pub fn save_settings(settings: &Settings) -> Result<(), crate::Error> {
+    save_json("settings.json", settings)
+}
+
+pub fn load_settings() -> Settings {
+    load_json("settings.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn bare_array_becomes_version_1() {
+        let data = parse_stations(r#"[{"name": "One", "url": "http://one"}]"#).unwrap();
+        assert_eq!(data.stations.len(), 1);
+        assert_eq!(data.stations[0].name, "One");
+        assert!(data.groups.is_empty());
+        assert!(data.stale);
+
+        let migrated = migrate_stations(serde_json::json!([]));
+        assert_eq!(migrated["version"], 1);
+        assert!(migrated["stations"].is_array());
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let data = parse_stations(
+            r#"{"version": 1, "stations": [{"name": "One", "url": "http://one", "group": "News"}], "groups": ["News"]}"#,
+        )
+        .unwrap();
+        assert_eq!(data.stations[0].group.as_deref(), Some("News"));
+        assert_eq!(data.groups, ["News"]);
+        assert!(!data.stale);
+    }
+
+    #[test]
+    fn corrupt_file_is_quarantined_and_backup_used() {
+        let dir = temp_dir("quarantine");
+        fs::write(dir.join("stations.json"), "{ not json").unwrap();
+        fs::write(
+            dir.join("stations.json.bak"),
+            r#"{"version": 1, "stations": [{"name": "Saved", "url": "http://saved"}]}"#,
+        )
+        .unwrap();
+
+        let (data, error) = load_stations_from(&dir);
+        assert_eq!(data.stations.len(), 1);
+        assert_eq!(data.stations[0].name, "Saved");
+        assert!(data.stale);
+        assert!(matches!(error, Some(crate::Error::Storage(_))));
+        assert!(!dir.join("stations.json").exists());
+        let quarantined: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("stations.json.corrupt-")
+            })
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(
+            fs::read_to_string(quarantined[0].path()).unwrap(),
+            "{ not json"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_file_without_backup_starts_empty() {
+        let dir = temp_dir("no-backup");
+        fs::write(dir.join("stations.json"), "[{").unwrap();
+
+        let (data, error) = load_stations_from(&dir);
+        assert!(data.stations.is_empty());
+        assert!(error.is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_not_an_error() {
+        let dir = temp_dir("missing");
+        let (data, error) = load_stations_from(&dir);
+        assert!(data.stations.is_empty());
+        assert!(error.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
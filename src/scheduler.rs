@@ -0,0 +1,327 @@
+use gtk::prelude::*;
+use relm4::{
+    AsyncComponentSender,
+    gtk::{self, glib},
+    typed_view::list::RelmListItem,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Msg, Radio, Station, duplicates, icon_names};
+
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Play,
+    Record,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub station: Station,
+    pub action: Action,
+    // Monday first, same as glib::DateTime::day_of_week
+    pub weekdays: [bool; 7],
+    // minutes since midnight
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Schedule {
+    // weekday is 1 (Monday) to 7 (Sunday). A schedule that ends before it
+    // starts runs over midnight into the next day.
+    pub fn is_active(&self, weekday: usize, minute: u32) -> bool {
+        let today = self.weekdays[weekday - 1];
+        let yesterday = self.weekdays[(weekday + 5) % 7];
+        if self.start <= self.end {
+            today && minute >= self.start && minute < self.end
+        } else {
+            (today && minute >= self.start) || (yesterday && minute < self.end)
+        }
+    }
+
+    pub fn is_active_now(&self) -> bool {
+        glib::DateTime::now_local().is_ok_and(|now| {
+            self.is_active(
+                now.day_of_week() as usize,
+                (now.hour() * 60 + now.minute()) as u32,
+            )
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let action = match self.action {
+            Action::Play => "Play",
+            Action::Record => "Record",
+        };
+        format!(
+            "{action} {}\n{} {}–{}",
+            self.station.name,
+            describe_weekdays(self.weekdays),
+            format_time(self.start),
+            format_time(self.end)
+        )
+    }
+}
+
+// Whether a station saved with a schedule or alarm is this one. Files from
+// before stations had keys only have the url to go on.
+pub fn same_station(saved: &Station, station: &Station) -> bool {
+    match &saved.key {
+        Some(key) => station.key.as_ref() == Some(key),
+        None => duplicates::is_duplicate(saved, station),
+    }
+}
+
+// Puts station in place of every saved copy of old, returns whether there
+// were any
+pub fn replace_station<'a>(
+    saved: impl Iterator<Item = &'a mut Station>,
+    old: &Station,
+    station: &Station,
+) -> bool {
+    let mut replaced = false;
+    for saved in saved {
+        if same_station(saved, old) {
+            *saved = station.clone();
+            replaced = true;
+        }
+    }
+    replaced
+}
+
+// "HH:MM" to minutes since midnight
+pub fn parse_time(time: &str) -> Option<u32> {
+    let (hour, minute) = time.trim().split_once(':')?;
+    let hour: u32 = hour.parse().ok()?;
+    let minute: u32 = minute.parse().ok()?;
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+pub fn format_time(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+// the Mon..Sun row of toggles used by the schedule and alarm popovers
+pub fn weekday_toggles(
+    sender: &AsyncComponentSender<Radio>,
+    toggled: fn(usize, bool) -> Msg,
+) -> gtk::Box {
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    row.add_css_class("linked");
+    for (day, label) in WEEKDAYS.iter().enumerate() {
+        let button = gtk::ToggleButton::with_label(label);
+        let sender = sender.clone();
+        button.connect_toggled(move |button| sender.input(toggled(day, button.is_active())));
+        row.append(&button);
+    }
+    row
+}
+
+pub fn describe_weekdays(weekdays: [bool; 7]) -> String {
+    let days: Vec<&str> = WEEKDAYS
+        .iter()
+        .zip(weekdays)
+        .filter_map(|(day, enabled)| enabled.then_some(*day))
+        .collect();
+    days.join(" ")
+}
+
+// A row in the schedule or alarm list: a description and a delete button
+#[derive(Debug)]
+pub struct RuleItem {
+    text: String,
+    tooltip: &'static str,
+    delete: fn(usize) -> Msg,
+    index: usize,
+    sender: AsyncComponentSender<Radio>,
+    handler: Option<glib::SignalHandlerId>,
+}
+
+impl RuleItem {
+    pub fn new(
+        text: String,
+        tooltip: &'static str,
+        delete: fn(usize) -> Msg,
+        index: usize,
+        sender: AsyncComponentSender<Radio>,
+    ) -> Self {
+        Self {
+            text,
+            tooltip,
+            delete,
+            index,
+            sender,
+            handler: None,
+        }
+    }
+}
+
+pub struct RuleWidgets {
+    label: gtk::Label,
+    delete_button: gtk::Button,
+}
+
+impl RelmListItem for RuleItem {
+    type Root = gtk::Box;
+    type Widgets = RuleWidgets;
+
+    fn setup(_item: &gtk::ListItem) -> (gtk::Box, RuleWidgets) {
+        relm4::view! {
+            my_box = gtk::Box {
+                set_spacing: 2,
+                set_orientation: gtk::Orientation::Horizontal,
+                #[name = "label"]
+                gtk::Label {
+                    set_halign: gtk::Align::Start,
+                },
+                #[name = "delete_button"]
+                gtk::Button {
+                    set_halign: gtk::Align::End,
+                    set_hexpand: true,
+                    set_has_frame: false,
+                    set_icon_name: icon_names::USER_TRASH,
+                },
+            },
+        }
+
+        let widgets = RuleWidgets {
+            label,
+            delete_button,
+        };
+
+        (my_box, widgets)
+    }
+
+    fn bind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+        let RuleWidgets {
+            label,
+            delete_button,
+        } = widgets;
+
+        label.set_text(&self.text);
+        delete_button.set_tooltip_text(Some(self.tooltip));
+
+        let sender = self.sender.clone();
+        let delete = self.delete;
+        let index = self.index;
+        self.handler = Some(delete_button.connect_clicked(move |_| {
+            sender.input(delete(index));
+        }));
+    }
+
+    fn unbind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+        if let Some(handler) = self.handler.take() {
+            widgets.delete_button.disconnect(handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(weekdays: [bool; 7], start: &str, end: &str) -> Schedule {
+        Schedule {
+            station: Station {
+                name: "Test".to_string(),
+                url: "http://example.com/stream".to_string(),
+                ..Default::default()
+            },
+            action: Action::Play,
+            weekdays,
+            start: parse_time(start).unwrap(),
+            end: parse_time(end).unwrap(),
+        }
+    }
+
+    #[test]
+    fn active_within_the_day() {
+        let monday = [true, false, false, false, false, false, false];
+        let schedule = schedule(monday, "08:00", "09:30");
+        assert!(!schedule.is_active(1, parse_time("07:59").unwrap()));
+        assert!(schedule.is_active(1, parse_time("08:00").unwrap()));
+        assert!(schedule.is_active(1, parse_time("09:29").unwrap()));
+        assert!(!schedule.is_active(1, parse_time("09:30").unwrap()));
+        assert!(!schedule.is_active(2, parse_time("08:30").unwrap()));
+    }
+
+    #[test]
+    fn active_across_midnight() {
+        let sunday = [false, false, false, false, false, false, true];
+        let schedule = schedule(sunday, "23:00", "01:00");
+        assert!(schedule.is_active(7, parse_time("23:30").unwrap()));
+        // runs on into Monday morning, wrapping round the week
+        assert!(schedule.is_active(1, parse_time("00:30").unwrap()));
+        assert!(!schedule.is_active(1, parse_time("01:00").unwrap()));
+        assert!(!schedule.is_active(1, parse_time("23:30").unwrap()));
+        assert!(!schedule.is_active(7, parse_time("00:30").unwrap()));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("08:05"), Some(8 * 60 + 5));
+        assert_eq!(parse_time(" 23:59 "), Some(23 * 60 + 59));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("noon"), None);
+        assert_eq!(format_time(8 * 60 + 5), "08:05");
+    }
+
+    #[test]
+    fn follows_url_edits() {
+        let monday = [true, false, false, false, false, false, false];
+        let mut schedules = [schedule(monday, "08:00", "09:00")];
+        let before = Station {
+            key: Some("key".to_string()),
+            ..schedules[0].station.clone()
+        };
+        schedules[0].station = before.clone();
+        let edited = Station {
+            name: "Renamed".to_string(),
+            url: "http://example.com/moved".to_string(),
+            ..before.clone()
+        };
+        assert!(same_station(&schedules[0].station, &edited));
+        let stations = schedules.iter_mut().map(|schedule| &mut schedule.station);
+        assert!(replace_station(stations, &before, &edited));
+        assert_eq!(schedules[0].station.url, "http://example.com/moved");
+        assert!(schedules[0].describe().starts_with("Play Renamed"));
+
+        // saved before stations had keys
+        let keyless = schedule(monday, "08:00", "09:00").station;
+        let keyed = Station {
+            key: Some("key".to_string()),
+            ..keyless.clone()
+        };
+        assert!(same_station(&keyless, &keyed));
+        assert!(!same_station(&keyless, &edited));
+    }
+
+    #[test]
+    fn follows_merges() {
+        let monday = [true, false, false, false, false, false, false];
+        let station = |key: &str, url: &str| Station {
+            name: key.to_string(),
+            url: url.to_string(),
+            key: Some(key.to_string()),
+            ..Default::default()
+        };
+        let removed = station("removed", "http://example.com/stream");
+        let kept = station("kept", "https://example.com/stream/");
+        let other = station("other", "http://example.com/other");
+        let mut schedules = [
+            schedule(monday, "08:00", "09:00"),
+            schedule(monday, "10:00", "11:00"),
+        ];
+        schedules[0].station = removed.clone();
+        schedules[1].station = other.clone();
+        let stations = schedules.iter_mut().map(|schedule| &mut schedule.station);
+        assert!(replace_station(stations, &removed, &kept));
+        assert_eq!(schedules[0].station.key.as_deref(), Some("kept"));
+        assert_eq!(schedules[1].station.key.as_deref(), Some("other"));
+
+        let stations = schedules.iter_mut().map(|schedule| &mut schedule.station);
+        assert!(!replace_station(stations, &removed, &kept));
+    }
+}
@@ -1,22 +1,515 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use radiobrowser::RadioBrowserAPI;
 use radiobrowser::StationOrder;
-use std::error::Error;
+use relm4::tokio;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, Station,
+    mirror::{DumpStation, Mirror},
+};
+
+// results are fetched this many at a time as the list is scrolled
+pub const PAGE_SIZE: u32 = 50;
+// cached results younger than this are used without asking radio-browser
+pub const FRESH_SECONDS: i64 = 60 * 60;
+// how many pages of results the cache holds on to
+const CACHE_SIZE: usize = 100;
+// how many times finding a radio-browser server is tried before giving up,
+// waiting a little longer after each failure
+const CONNECT_ATTEMPTS: u32 = 3;
+// how long a server gets to answer a search before the next one is tried,
+// the station database download has its own, longer limit
+#[cfg(not(test))]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+#[cfg(test)]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Order {
+    #[default]
+    Clicks,
+    Votes,
+    Bitrate,
+    RecentlyChanged,
+}
+
+impl Order {
+    // in the same order as the dropdown
+    pub const LABELS: [&str; 4] = [
+        "Most played",
+        "Most votes",
+        "Highest bitrate",
+        "Recently changed",
+    ];
+    const ALL: [Order; 4] = [
+        Order::Clicks,
+        Order::Votes,
+        Order::Bitrate,
+        Order::RecentlyChanged,
+    ];
+
+    pub fn from_position(position: u32) -> Self {
+        Self::ALL
+            .get(position as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn position(self) -> u32 {
+        Self::ALL
+            .iter()
+            .position(|order| *order == self)
+            .unwrap_or(0) as u32
+    }
+
+    fn station_order(self) -> StationOrder {
+        match self {
+            Order::Clicks => StationOrder::Clickcount,
+            Order::Votes => StationOrder::Votes,
+            Order::Bitrate => StationOrder::Bitrate,
+            Order::RecentlyChanged => StationOrder::Changetimestamp,
+        }
+    }
+
+    // as the order parameter of the radio-browser api
+    fn api_name(self) -> &'static str {
+        match self {
+            Order::Clicks => "clickcount",
+            Order::Votes => "votes",
+            Order::Bitrate => "bitrate",
+            Order::RecentlyChanged => "changetimestamp",
+        }
+    }
+}
+
+// Everything besides the name that a search can be narrowed down by, empty
+// strings and a zero bitrate mean no filter. Saved in settings.json.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    pub tag: String,
+    pub country: String,
+    pub countrycode: String,
+    pub language: String,
+    pub codec: String,
+    pub min_bitrate: u16,
+    pub order: Order,
+}
+
+#[derive(Debug)]
+pub enum Filter {
+    Tag(String),
+    Country(String),
+    Countrycode(String),
+    Language(String),
+    Codec(String),
+    MinBitrate(u16),
+    Order(Order),
+}
+
+impl Filters {
+    pub fn set(&mut self, filter: Filter) {
+        match filter {
+            Filter::Tag(tag) => self.tag = tag,
+            Filter::Country(country) => self.country = country,
+            Filter::Countrycode(countrycode) => self.countrycode = countrycode,
+            Filter::Language(language) => self.language = language,
+            Filter::Codec(codec) => self.codec = codec,
+            Filter::MinBitrate(bitrate) => self.min_bitrate = bitrate,
+            Filter::Order(order) => self.order = order,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self
+            == Self {
+                order: self.order,
+                ..Default::default()
+            }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    timestamp: i64,
+    stations: Vec<Station>,
+}
+
+// Recent pages of results, most recently fetched first. Kept on disk too so
+// repeat searches still work without a connection.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    entries: Vec<CacheEntry>,
+}
+
+impl Cache {
+    pub fn key(query: &str, filters: &Filters, offset: u32) -> String {
+        format!(
+            "{}\n{}\n{offset}",
+            query.trim().to_lowercase(),
+            serde_json::to_string(filters).unwrap_or_default()
+        )
+    }
+
+    // max_age in seconds, None takes results of any age
+    pub fn get(&self, key: &str, max_age: Option<i64>) -> Option<Vec<Station>> {
+        let now = now();
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .filter(|entry| max_age.is_none_or(|max_age| now - entry.timestamp < max_age))
+            .map(|entry| entry.stations.clone())
+    }
+
+    pub fn insert(&mut self, key: String, stations: Vec<Station>) {
+        self.entries.retain(|entry| entry.key != key);
+        self.entries.insert(
+            0,
+            CacheEntry {
+                key,
+                timestamp: now(),
+                stations,
+            },
+        );
+        self.entries.truncate(CACHE_SIZE);
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Servers as typed into the preferences, separated by commas or whitespace.
+// Anything without a scheme is taken to be https.
+pub fn parse_servers(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|server| !server.is_empty())
+        .map(|server| {
+            let server = server.trim_end_matches('/');
+            if server.contains("://") {
+                server.to_string()
+            } else {
+                format!("https://{server}")
+            }
+        })
+        .collect()
+}
+
+pub fn client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(concat!("RelmyVibes/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+}
 
-use crate::Station;
-pub async fn search(api: RadioBrowserAPI, query: String) -> Result<Vec<Station>, Box<dyn Error>> {
-    let stations = api
+// Looking up the radio-browser servers needs the network, so this happens on
+// the first search rather than at startup
+pub async fn connect() -> Result<RadioBrowserAPI, Error> {
+    let mut attempt = 1;
+    loop {
+        // the error isn't Send, so it can't be held on to across the sleep
+        let error = match RadioBrowserAPI::new().await {
+            Ok(api) => return Ok(api),
+            Err(error) => error.to_string(),
+        };
+        if attempt == CONNECT_ATTEMPTS {
+            return Err(Error::Search(format!(
+                "could not reach radio-browser ({error})"
+            )));
+        }
+        tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        attempt += 1;
+    }
+}
+
+// offset is how many results have been fetched already. With a mirror the
+// search never leaves the machine. Servers picked in the preferences are
+// tried in order, otherwise api is connected first if it hasn't been yet.
+pub async fn search(
+    api: &mut Option<RadioBrowserAPI>,
+    servers: Vec<String>,
+    mirror: Option<Arc<Mirror>>,
+    query: String,
+    filters: Filters,
+    offset: u32,
+) -> Result<Vec<Station>, Error> {
+    if let Some(mirror) = mirror {
+        return Ok(mirror.search(&query, &filters, offset));
+    }
+    if !servers.is_empty() {
+        return search_servers(&servers, &query, &filters, offset).await;
+    }
+    let client = match api.clone() {
+        Some(client) => client,
+        None => {
+            let client = connect().await?;
+            *api = Some(client.clone());
+            client
+        }
+    };
+    let mut builder = client
         .get_stations()
         .name(query)
         .reverse(true)
         .hidebroken(true)
-        .order(StationOrder::Clickcount)
+        .order(filters.order.station_order())
+        .offset(offset.to_string())
+        .limit(PAGE_SIZE.to_string());
+    if !filters.tag.trim().is_empty() {
+        builder = builder.tag(filters.tag.trim());
+    }
+    if !filters.country.trim().is_empty() {
+        builder = builder.country(filters.country.trim());
+    }
+    if !filters.countrycode.trim().is_empty() {
+        builder = builder.countrycode(filters.countrycode.trim());
+    }
+    if !filters.language.trim().is_empty() {
+        builder = builder.language(filters.language.trim());
+    }
+    if !filters.codec.trim().is_empty() {
+        builder = builder.codec(filters.codec.trim());
+    }
+    if filters.min_bitrate > 0 {
+        builder = builder.bitrate_min(filters.min_bitrate);
+    }
+    let stations = builder
         .send()
-        .await?;
+        .await
+        .map_err(|e| Error::Search(e.to_string()))?;
     Ok(stations
         .iter()
         .map(|station| Station {
             name: station.name.to_string(),
             url: station.url_resolved.to_string(),
+            uuid: non_empty(&station.stationuuid),
+            tags: split_tags(&station.tags),
+            country: non_empty(&station.country),
+            language: non_empty(&station.language),
+            codec: non_empty(&station.codec),
+            bitrate: (station.bitrate > 0).then_some(station.bitrate),
+            homepage: non_empty(&station.homepage),
+            favicon: non_empty(&station.favicon),
+            ..Default::default()
         })
         .collect())
 }
+
+async fn search_servers(
+    servers: &[String],
+    query: &str,
+    filters: &Filters,
+    offset: u32,
+) -> Result<Vec<Station>, Error> {
+    let client = client().map_err(|e| Error::Search(e.to_string()))?;
+    let mut errors = vec![];
+    for server in servers {
+        match search_server(&client, server, query, filters, offset).await {
+            Ok(stations) => return Ok(stations.iter().map(DumpStation::to_station).collect()),
+            Err(error) => errors.push(format!("{server}: {error}")),
+        }
+    }
+    Err(Error::Search(errors.join(", ")))
+}
+
+// The same search the radio-browser crate makes, against any server
+async fn search_server(
+    client: &reqwest::Client,
+    server: &str,
+    query: &str,
+    filters: &Filters,
+    offset: u32,
+) -> reqwest::Result<Vec<DumpStation>> {
+    let mut params = vec![
+        ("name", query.to_string()),
+        ("reverse", "true".to_string()),
+        ("hidebroken", "true".to_string()),
+        ("order", filters.order.api_name().to_string()),
+        ("offset", offset.to_string()),
+        ("limit", PAGE_SIZE.to_string()),
+    ];
+    for (name, value) in [
+        ("tag", &filters.tag),
+        ("country", &filters.country),
+        ("countrycode", &filters.countrycode),
+        ("language", &filters.language),
+        ("codec", &filters.codec),
+    ] {
+        if !value.trim().is_empty() {
+            params.push((name, value.trim().to_string()));
+        }
+    }
+    if filters.min_bitrate > 0 {
+        params.push(("bitrateMin", filters.min_bitrate.to_string()));
+    }
+    client
+        .get(format!("{server}/json/stations/search"))
+        .query(&params)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+// radio-browser uses empty strings for anything it doesn't know
+pub fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+// tags come as one comma separated string
+pub fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use crate::test_util::{self, block_on};
+
+    // A local stand-in for a radio-browser server, answering every request
+    // with the same status and body. The path and query of each request are
+    // sent back down the channel.
+    fn serve(status: &'static str, body: &'static str) -> (String, mpsc::Receiver<String>) {
+        let (requests, received) = mpsc::channel();
+        let base = test_util::serve(move |_, path| {
+            let _ = requests.send(path.to_string());
+            test_util::response(status, &[("Content-Type", "application/json")], body)
+        });
+        (base, received)
+    }
+
+    // somewhere nothing is listening
+    fn dead_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    // takes the request and never answers
+    fn stalled_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut held = vec![];
+            for stream in listener.incoming() {
+                held.push(stream);
+            }
+        });
+        base
+    }
+
+    const RESULTS: &str = r#"[{"stationuuid": "a", "name": "Smooth Jazz", "url": "http://a",
+        "url_resolved": "http://a/live", "tags": "jazz,smooth", "codec": "MP3", "bitrate": 128}]"#;
+
+    #[test]
+    fn fails_over_to_the_next_server() {
+        let (broken, _) = serve("500 Internal Server Error", "");
+        let (live, requests) = serve("200 OK", RESULTS);
+        let servers = [dead_server(), broken, live];
+        let stations = block_on(search_servers(&servers, "jazz", &Filters::default(), 0)).unwrap();
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].name, "Smooth Jazz");
+        assert_eq!(stations[0].url, "http://a/live");
+        assert_eq!(stations[0].tags, ["jazz", "smooth"]);
+        assert_eq!(stations[0].bitrate, Some(128));
+        assert!(requests.try_recv().is_ok());
+    }
+
+    #[test]
+    fn fails_over_from_a_server_that_never_answers() {
+        let stalled = stalled_server();
+        let (live, requests) = serve("200 OK", RESULTS);
+        let servers = [stalled.clone(), live];
+        let stations = block_on(search_servers(&servers, "jazz", &Filters::default(), 0)).unwrap();
+        assert_eq!(stations[0].name, "Smooth Jazz");
+        assert!(requests.try_recv().is_ok());
+
+        let servers = [stalled];
+        match block_on(search_servers(&servers, "jazz", &Filters::default(), 0)) {
+            Err(Error::Search(message)) => assert!(message.contains(&servers[0]), "{message}"),
+            other => panic!("expected a search error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn every_server_failing_names_them_all() {
+        let (broken, _) = serve("503 Service Unavailable", "");
+        let dead = dead_server();
+        let servers = [dead.clone(), broken.clone()];
+        match block_on(search_servers(&servers, "jazz", &Filters::default(), 0)) {
+            Err(Error::Search(message)) => {
+                assert!(message.contains(&dead), "{message}");
+                assert!(message.contains(&broken), "{message}");
+            }
+            other => panic!("expected a search error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sends_the_filters() {
+        let (live, requests) = serve("200 OK", "[]");
+        let filters = Filters {
+            tag: " smooth ".to_string(),
+            countrycode: "GB".to_string(),
+            min_bitrate: 128,
+            order: Order::Votes,
+            ..Default::default()
+        };
+        let stations = block_on(search_servers(&[live], "jazz fm", &filters, 50)).unwrap();
+        assert!(stations.is_empty());
+
+        let path = requests.try_recv().unwrap();
+        let url = reqwest::Url::parse(&format!("http://localhost{path}")).unwrap();
+        assert_eq!(url.path(), "/json/stations/search");
+        let mut params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        params.sort();
+        let mut expected: Vec<(String, String)> = [
+            ("bitrateMin", "128"),
+            ("countrycode", "GB"),
+            ("hidebroken", "true"),
+            ("limit", "50"),
+            ("name", "jazz fm"),
+            ("offset", "50"),
+            ("order", "votes"),
+            ("reverse", "true"),
+            ("tag", "smooth"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        expected.sort();
+        assert_eq!(params, expected);
+    }
+
+    #[test]
+    fn servers_as_typed() {
+        assert_eq!(
+            parse_servers(
+                "de1.api.radio-browser.info, https://fr1.api.radio-browser.info/\n http://localhost:8080"
+            ),
+            [
+                "https://de1.api.radio-browser.info",
+                "https://fr1.api.radio-browser.info",
+                "http://localhost:8080"
+            ]
+        );
+        assert!(parse_servers(" , ").is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+// how long the sleep timer spends fading out before it stops playback
+pub const FADE_SECONDS: u32 = 60;
+
+// What a second passing means for playback
+#[derive(Debug, PartialEq)]
+pub enum Tick {
+    Wait,
+    Fade(f64),
+    Stop,
+}
+
+#[derive(Debug, Default)]
+pub struct SleepTimer {
+    // seconds until playback stops
+    pub remaining: Option<u32>,
+    // the volume to go back to once it's over, taken when the fade begins so
+    // changing the volume while counting down still counts
+    volume: Option<f64>,
+}
+
+impl SleepTimer {
+    // Starts the countdown over. A restart during the fade keeps the volume
+    // from before it and returns it to go back to.
+    pub fn start(&mut self, minutes: u32) -> Option<f64> {
+        self.remaining = Some(minutes * 60);
+        self.volume
+    }
+
+    // volume is what's playing now
+    pub fn tick(&mut self, volume: f64) -> Tick {
+        let Some(remaining) = self.remaining else {
+            return Tick::Wait;
+        };
+        let remaining = remaining.saturating_sub(1);
+        if remaining == 0 {
+            return Tick::Stop;
+        }
+        self.remaining = Some(remaining);
+        if remaining > FADE_SECONDS {
+            return Tick::Wait;
+        }
+        let volume = *self.volume.get_or_insert(volume);
+        Tick::Fade(volume * remaining as f64 / FADE_SECONDS as f64)
+    }
+
+    // returns the volume from before the fade, if it had begun
+    pub fn cancel(&mut self) -> Option<f64> {
+        self.remaining = None;
+        self.volume.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_until_fade(timer: &mut SleepTimer, volume: f64) -> Tick {
+        loop {
+            match timer.tick(volume) {
+                Tick::Wait => {}
+                tick => return tick,
+            }
+        }
+    }
+
+    #[test]
+    fn fades_from_the_volume_when_the_fade_begins() {
+        let mut timer = SleepTimer::default();
+        assert_eq!(timer.start(2), None);
+        // turned down while counting down
+        for _ in 0..30 {
+            assert_eq!(timer.tick(0.8), Tick::Wait);
+        }
+        let first = tick_until_fade(&mut timer, 0.4);
+        assert_eq!(first, Tick::Fade(0.4));
+        // the faded volume coming back doesn't change where it fades from
+        assert_eq!(timer.tick(0.39), Tick::Fade(0.4 * 59.0 / 60.0));
+        assert_eq!(timer.cancel(), Some(0.4));
+        assert_eq!(timer.remaining, None);
+    }
+
+    #[test]
+    fn restarts() {
+        let mut timer = SleepTimer::default();
+        timer.start(1);
+        timer.tick(0.8);
+        timer.tick(0.79);
+        // mid-fade the volume goes back and the next fade starts from it
+        assert_eq!(timer.start(5), Some(0.8));
+        assert_eq!(tick_until_fade(&mut timer, 0.5), Tick::Fade(0.8));
+
+        // before the fade nothing is kept, so a later change counts
+        let mut timer = SleepTimer::default();
+        timer.start(5);
+        timer.tick(0.8);
+        assert_eq!(timer.start(5), None);
+        assert_eq!(tick_until_fade(&mut timer, 0.5), Tick::Fade(0.5));
+    }
+
+    #[test]
+    fn stops_when_time_is_up() {
+        let mut timer = SleepTimer::default();
+        assert_eq!(timer.tick(1.0), Tick::Wait);
+        timer.start(1);
+        for _ in 0..59 {
+            assert!(matches!(timer.tick(1.0), Tick::Fade(_)));
+        }
+        assert_eq!(timer.tick(1.0), Tick::Stop);
+        assert_eq!(timer.cancel(), Some(1.0));
+    }
+}
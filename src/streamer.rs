@@ -1,10 +1,13 @@
-use std::sync::{Arc, Mutex};
+use std::fmt;
 
 use anyhow::Error;
 use gstreamer_player::{Player, gst::prelude::*};
 use relm4::AsyncComponentSender;
 
-pub fn load(sender: AsyncComponentSender<crate::Radio>) -> Result<Player, Error> {
+pub fn load(
+    sender: AsyncComponentSender<crate::Radio>,
+    tap: &crate::recorder::Tap,
+) -> Result<Player, Error> {
     gstreamer::init()?;
 
     let dispatcher = gstreamer_player::PlayerGMainContextSignalDispatcher::new(None);
@@ -14,29 +17,108 @@ pub fn load(sender: AsyncComponentSender<crate::Radio>) -> Result<Player, Error>
     );
 
     player.set_volume(1.0);
-    let error = Arc::new(Mutex::new(Ok(player.clone())));
+    tap.attach(&player.pipeline());
     // Connect to the player's "end-of-stream" signal, which will tell us when the
     // currently played media stream reached its end.
     player.connect_end_of_stream(move |player| {
         player.stop();
     });
 
-    let error_clone = Arc::clone(&error);
+    let error_sender = sender.clone();
     // Connect to the player's "error" signal, which will inform us about eventual
     // errors (such as failing to retrieve a http stream).
     player.connect_error(move |player, err| {
-        let error = &error_clone;
-
-        *error.lock().unwrap() = Err(err.clone());
+        error_sender.input(crate::Msg::StreamError(crate::Error::Stream {
+            url: player.uri().map(|uri| uri.to_string()).unwrap_or_default(),
+            domain: err.domain().as_str().to_string(),
+            message: err.message().to_string(),
+        }));
 
         player.stop();
     });
 
+    let tag_sender = sender.clone();
+    // ICY StreamTitle tags end up as the media title
+    player.connect_media_info_updated(move |_, info| {
+        let now_playing = info.title().and_then(|title| NowPlaying::parse(&title));
+        tag_sender.input(crate::Msg::NowPlaying(now_playing));
+    });
+
     player.connect_volume_changed(move |player| {
         sender.input(crate::Msg::VolumeChanged(player.volume()))
     });
 
-    let guard = error.as_ref().lock().unwrap();
+    Ok(player)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NowPlaying {
+    pub artist: Option<String>,
+    pub title: String,
+}
+
+impl NowPlaying {
+    // Stations almost always send "Artist - Title", anything else is treated as
+    // just a title
+    pub fn parse(stream_title: &str) -> Option<Self> {
+        let stream_title = stream_title.trim();
+        if stream_title.is_empty() {
+            return None;
+        }
+        match stream_title.split_once(" - ") {
+            Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+                Some(Self {
+                    artist: Some(artist.trim().to_string()),
+                    title: title.trim().to_string(),
+                })
+            }
+            _ => Some(Self {
+                artist: None,
+                title: stream_title.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for NowPlaying {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.artist {
+            Some(artist) => write!(f, "{artist} - {}", self.title),
+            None => write!(f, "{}", self.title),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_artist_and_title() {
+        let now_playing = NowPlaying::parse(" Boards of Canada - Roygbiv ").unwrap();
+        assert_eq!(now_playing.artist.as_deref(), Some("Boards of Canada"));
+        assert_eq!(now_playing.title, "Roygbiv");
+        assert_eq!(now_playing.to_string(), "Boards of Canada - Roygbiv");
+    }
+
+    #[test]
+    fn parse_title_only() {
+        for title in ["Station jingle", "- Untitled", "Intro -", "A-Ha"] {
+            let now_playing = NowPlaying::parse(title).unwrap();
+            assert_eq!(now_playing.artist, None);
+            assert_eq!(now_playing.title, title);
+        }
+    }
+
+    #[test]
+    fn parse_splits_on_the_first_dash() {
+        let now_playing = NowPlaying::parse("Artist - Song - Live").unwrap();
+        assert_eq!(now_playing.artist.as_deref(), Some("Artist"));
+        assert_eq!(now_playing.title, "Song - Live");
+    }
 
-    guard.clone().map_err(|e| e.into())
+    #[test]
+    fn parse_empty() {
+        assert_eq!(NowPlaying::parse("   "), None);
+    }
 }
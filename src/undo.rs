@@ -0,0 +1,82 @@
+use crate::Station;
+
+// how many steps back undo can go
+const LIMIT: usize = 50;
+
+// One change to the station list, with what it takes to reverse it
+#[derive(Debug)]
+pub enum Change {
+    Added(usize),
+    Removed {
+        id: usize,
+        station: Station,
+        position: u32,
+    },
+    Edited {
+        id: usize,
+        before: Station,
+    },
+    Moved {
+        id: usize,
+        from: u32,
+    },
+}
+
+// Each step is everything one action did, undone together in reverse order
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    steps: Vec<Vec<Change>>,
+}
+
+impl UndoStack {
+    pub fn push(&mut self, step: Vec<Change>) {
+        if step.is_empty() {
+            return;
+        }
+        self.steps.push(step);
+        if self.steps.len() > LIMIT {
+            self.steps.remove(0);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<Change>> {
+        self.steps.pop()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_steps() {
+        let mut stack = UndoStack::default();
+        for id in 0..LIMIT + 5 {
+            stack.push(vec![Change::Added(id)]);
+        }
+        let mut undone = vec![];
+        while let Some(step) = stack.pop() {
+            for change in step {
+                if let Change::Added(id) = change {
+                    undone.push(id);
+                }
+            }
+        }
+        // newest first, the oldest five fell off the bottom
+        assert_eq!(undone, (5..LIMIT + 5).rev().collect::<Vec<_>>());
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn empty_steps_are_ignored() {
+        let mut stack = UndoStack::default();
+        stack.push(vec![]);
+        assert!(stack.is_empty());
+        stack.push(vec![Change::Added(1), Change::Moved { id: 1, from: 0 }]);
+        assert_eq!(stack.pop().map(|step| step.len()), Some(2));
+    }
+}